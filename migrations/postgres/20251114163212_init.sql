@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS gateway_id_mapping (
+    id BIGSERIAL PRIMARY KEY,
+    gateway_id TEXT NOT NULL UNIQUE,
+    token TEXT NOT NULL,
+    merchant_private_key TEXT NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Differences between stored transactions and the gateway found by reconciliation
+CREATE TABLE IF NOT EXISTS discrepancies (
+    id BIGSERIAL PRIMARY KEY,
+    gateway_reference TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    our_status TEXT,
+    gateway_status TEXT,
+    details TEXT,
+    fixed BOOLEAN NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS discrepancies_created_at ON discrepancies (created_at);
+CREATE INDEX IF NOT EXISTS transactions_updated_at ON transactions (updated_at);
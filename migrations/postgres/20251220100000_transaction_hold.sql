@@ -0,0 +1,2 @@
+-- Transactions approved by the gateway with a different amount or currency wait for manual review
+ALTER TABLE transactions ADD COLUMN on_hold BOOLEAN NOT NULL DEFAULT FALSE;
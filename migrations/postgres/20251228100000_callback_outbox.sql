@@ -0,0 +1,18 @@
+-- Callbacks waiting to be delivered to Gateway.Connect, removed once delivered
+CREATE TABLE IF NOT EXISTS callback_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    gateway_reference TEXT NOT NULL,
+    token TEXT NOT NULL,
+    status TEXT NOT NULL,
+    reason TEXT,
+    amount BIGINT NOT NULL,
+    currency TEXT NOT NULL,
+    attempts BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS callback_outbox_next_attempt_at ON callback_outbox (next_attempt_at);
+CREATE INDEX IF NOT EXISTS callback_outbox_gateway_reference ON callback_outbox (gateway_reference);
@@ -0,0 +1,18 @@
+-- Chargebacks and other disputes raised against transactions
+CREATE TABLE IF NOT EXISTS disputes (
+    id BIGSERIAL PRIMARY KEY,
+    dispute_reference TEXT NOT NULL UNIQUE,
+    gateway_reference TEXT NOT NULL,
+    status TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    amount BIGINT NOT NULL,
+    currency TEXT NOT NULL,
+    forwarded BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS disputes_status ON disputes (status, created_at);
+
+-- Queued dispute notifications refer to the dispute
+ALTER TABLE callback_outbox ADD COLUMN dispute_id BIGINT;
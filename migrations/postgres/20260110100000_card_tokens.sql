@@ -0,0 +1,15 @@
+-- Cards saved with the first payment and charged later by the merchant
+CREATE TABLE IF NOT EXISTS card_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    -- Opaque token known to Gateway.Connect
+    token TEXT NOT NULL UNIQUE,
+    -- Gateway card token, sealed
+    gateway_card_token TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    -- First payment with the card
+    gateway_reference TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    revoked_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS card_tokens_gateway_reference ON card_tokens (gateway_reference);
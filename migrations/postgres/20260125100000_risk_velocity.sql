@@ -0,0 +1,3 @@
+-- Velocity rules count recent transactions by customer email and ip
+CREATE INDEX IF NOT EXISTS transactions_customer_email ON transactions (customer_email);
+CREATE INDEX IF NOT EXISTS transactions_customer_ip ON transactions (customer_ip);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS transactions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    token TEXT NOT NULL,
+    client_reference TEXT NOT NULL UNIQUE,
+    gateway_reference TEXT NOT NULL UNIQUE,
+    processing_url TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    status TEXT NOT NULL,
+    status_details TEXT,
+    client_id TEXT NOT NULL,
+    client_secret TEXT NOT NULL,
+    sandbox BOOLEAN NOT NULL,
+    customer_email TEXT,
+    customer_ip TEXT,
+    customer_country TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS transactions_token ON transactions (token);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS job_leases (
+    resource TEXT NOT NULL PRIMARY KEY,
+    owner TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Mappings created before this migration age from the moment it runs
+ALTER TABLE gateway_id_mapping ADD COLUMN created_at INTEGER;
+UPDATE gateway_id_mapping SET created_at = CAST(strftime('%s', 'now') AS INTEGER);
+
+CREATE INDEX IF NOT EXISTS transactions_created_at ON transactions (created_at);
@@ -0,0 +1,26 @@
+-- Requests to the gateway made for the transaction, masked
+CREATE TABLE IF NOT EXISTS interaction_logs (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    gateway_reference TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    log TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS interaction_logs_gateway_reference ON interaction_logs (gateway_reference);
+
+-- Callbacks sent to Gateway.Connect
+CREATE TABLE IF NOT EXISTS callbacks (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    gateway_reference TEXT NOT NULL,
+    token TEXT NOT NULL,
+    status TEXT NOT NULL,
+    reason TEXT,
+    amount INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    delivered BOOLEAN NOT NULL,
+    error TEXT,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS callbacks_gateway_reference ON callbacks (gateway_reference);
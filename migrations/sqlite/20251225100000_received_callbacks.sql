@@ -0,0 +1,13 @@
+-- Callbacks received from the gateway and what was done with them
+CREATE TABLE IF NOT EXISTS received_callbacks (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    gateway_reference TEXT NOT NULL,
+    status TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    description TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS received_callbacks_gateway_reference ON received_callbacks (gateway_reference);
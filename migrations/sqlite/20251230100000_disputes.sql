@@ -0,0 +1,18 @@
+-- Chargebacks and other disputes raised against transactions
+CREATE TABLE IF NOT EXISTS disputes (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    dispute_reference TEXT NOT NULL UNIQUE,
+    gateway_reference TEXT NOT NULL,
+    status TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    forwarded BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS disputes_status ON disputes (status, created_at);
+
+-- Queued dispute notifications refer to the dispute
+ALTER TABLE callback_outbox ADD COLUMN dispute_id INTEGER;
@@ -0,0 +1,4 @@
+-- Auth-only transactions hold the funds until they are captured or cancelled
+ALTER TABLE transactions ADD COLUMN auth_only BOOLEAN NOT NULL DEFAULT FALSE;
+-- Captured amount in minor units, may be less than the authorized one
+ALTER TABLE transactions ADD COLUMN captured_amount INTEGER;
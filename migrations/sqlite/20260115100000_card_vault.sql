@@ -0,0 +1,14 @@
+-- Cards encrypted with the vault key, CVV is never stored
+CREATE TABLE IF NOT EXISTS vault_cards (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    token TEXT NOT NULL UNIQUE,
+    card TEXT NOT NULL,
+    -- Payment the card was stored with
+    gateway_reference TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS vault_cards_gateway_reference ON vault_cards (gateway_reference);
+
+-- Saved card is kept by the gateway or in the vault, gateway_card_token holds the vault token then
+ALTER TABLE card_tokens ADD COLUMN source TEXT NOT NULL DEFAULT 'gateway';
@@ -0,0 +1,6 @@
+-- Card identity without the PAN: keyed HMAC of it, first 6 and last 4 digits
+ALTER TABLE transactions ADD COLUMN card_fingerprint TEXT;
+ALTER TABLE transactions ADD COLUMN card_bin TEXT;
+ALTER TABLE transactions ADD COLUMN card_last4 TEXT;
+
+CREATE INDEX IF NOT EXISTS transactions_card_fingerprint ON transactions (card_fingerprint);
@@ -0,0 +1,313 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::DefaultBodyLimit,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    connect,
+    connect::auth::{InboundAuth, authenticate},
+    db::{
+        TransactionFilter, TransactionRecord,
+        audit::{CallbackRecord, DiscrepancyRecord, InteractionLogRecord, ReceivedCallbackRecord},
+        dispute::DisputeRecord,
+    },
+    export,
+    gateway::sync::apply_status,
+    settlement,
+    state::AppState,
+};
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 500;
+
+/// Settlement reports are uploaded as a request body
+const MAX_REPORT_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    BadRequest(String),
+    Internal(String),
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(value: sqlx::Error) -> Self {
+        Self::Db(value)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let (status, error) = match self {
+            Error::NotFound => (StatusCode::NOT_FOUND, "not found".to_string()),
+            Error::BadRequest(e) => (StatusCode::BAD_REQUEST, e),
+            Error::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e),
+            Error::Db(e) => {
+                tracing::error!("Admin API database error: {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            }
+        };
+        (status, Json(serde_json::json!({ "error": error }))).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    /// Starts from 1
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    items: Vec<T>,
+    page: u32,
+    per_page: u32,
+    total: i64,
+}
+
+#[instrument(skip_all)]
+async fn search(
+    State(AppState { db, .. }): State<AppState>,
+    Query(filter): Query<TransactionFilter>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<Page<TransactionRecord>>, Error> {
+    let page = pagination.page.unwrap_or(1).max(1);
+    let per_page = pagination
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let offset = i64::from(page - 1) * i64::from(per_page);
+    let (items, total) = db
+        .search_transactions(&filter, per_page.into(), offset)
+        .await?;
+    Ok(Json(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Mapping {
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionDetails {
+    transaction: TransactionRecord,
+    /// Merchant key is never exposed
+    mapping: Option<Mapping>,
+    callbacks: Vec<CallbackRecord>,
+    /// Callbacks received from the gateway
+    received_callbacks: Vec<ReceivedCallbackRecord>,
+    interaction_logs: Vec<InteractionLogRecord>,
+}
+
+#[instrument(skip_all, fields(%reference))]
+async fn details(
+    State(AppState { db, .. }): State<AppState>,
+    Path(reference): Path<String>,
+) -> Result<Json<TransactionDetails>, Error> {
+    let transaction = db
+        .get_transaction_record(&reference)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let mapping = db.get_mapping(&reference).await?.map(|mapping| Mapping {
+        token: mapping.token,
+    });
+    Ok(Json(TransactionDetails {
+        transaction,
+        mapping,
+        callbacks: db.callbacks(&reference).await?,
+        received_callbacks: db.received_callbacks(&reference).await?,
+        interaction_logs: db.interaction_logs(&reference).await?,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: export::Format,
+}
+
+/// Stream transactions matching the search filter as a file
+#[instrument(skip_all)]
+async fn export(
+    State(AppState { db, .. }): State<AppState>,
+    Query(filter): Query<TransactionFilter>,
+    Query(ExportQuery { format }): Query<ExportQuery>,
+) -> Response {
+    let body = Body::from_stream(export::transactions(db, filter, format));
+    (
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                format.content_type().to_string(),
+            ),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"transactions.{}\"",
+                    format.extension()
+                ),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReconcileQuery {
+    #[serde(default)]
+    correct: bool,
+    /// Unix timestamp, approved transactions created since then must be in the report
+    created_from: Option<i64>,
+    /// Unix timestamp, exclusive
+    created_to: Option<i64>,
+}
+
+/// Reconcile settlement report CSV sent as the request body
+#[instrument(skip_all)]
+async fn reconcile(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<ReconcileQuery>,
+    body: axum::body::Bytes,
+) -> Result<Json<settlement::Report>, Error> {
+    let period = match (query.created_from, query.created_to) {
+        (Some(from), Some(to)) => Some((from, to)),
+        (None, None) => None,
+        _ => {
+            return Err(Error::BadRequest(
+                "created_from and created_to must be used together".to_string(),
+            ));
+        }
+    };
+    let options = settlement::Options {
+        correct: query.correct,
+        period,
+    };
+    let report = settlement::reconcile(&db, body.as_ref(), &options)
+        .await
+        .map_err(|e| match e.downcast::<sqlx::Error>() {
+            Ok(e) => Error::Db(e),
+            Err(e) => Error::BadRequest(e.to_string()),
+        })?;
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resolution {
+    /// Final status decided on review
+    status: connect::Status,
+    /// Decline reason sent to Gateway.Connect
+    reason: Option<String>,
+}
+
+/// Finalize transaction held for manual review and notify Gateway.Connect
+#[instrument(skip_all, fields(%reference))]
+async fn resolve(
+    State(AppState { db, .. }): State<AppState>,
+    Path(reference): Path<String>,
+    Json(resolution): Json<Resolution>,
+) -> Result<StatusCode, Error> {
+    if !resolution.status.is_final() {
+        return Err(Error::BadRequest("status must be final".to_string()));
+    }
+    let transaction = db
+        .get_transaction_by_gateway_reference(&reference)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let record = db
+        .get_transaction_record(&reference)
+        .await?
+        .ok_or(Error::NotFound)?;
+    if !record.on_hold {
+        return Err(Error::BadRequest("transaction is not on hold".to_string()));
+    }
+    let details = match resolution.status {
+        connect::Status::Declined => Some(resolution.reason.unwrap_or_default()),
+        _ => None,
+    };
+    match apply_status(&db, &transaction, resolution.status, details).await {
+        Ok(true) => {
+            tracing::info!(status = ?resolution.status, "Resolved held transaction");
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Ok(false) => Err(Error::BadRequest(
+            "transaction is already final".to_string(),
+        )),
+        Err(e) => {
+            tracing::error!("Failed to resolve held transaction: {e}");
+            Err(Error::Internal(e.to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiscrepancyQuery {
+    /// Unix timestamp, everything by default
+    since: Option<i64>,
+    limit: Option<u32>,
+}
+
+/// Discrepancies found by the status reconciliation, newest first
+#[instrument(skip_all)]
+async fn discrepancies(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<DiscrepancyQuery>,
+) -> Result<Json<Vec<DiscrepancyRecord>>, Error> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let discrepancies = db
+        .discrepancies(query.since.unwrap_or_default(), limit.into())
+        .await?;
+    Ok(Json(discrepancies))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisputeQuery {
+    limit: Option<u32>,
+}
+
+/// Disputes waiting for the resolution, newest first
+#[instrument(skip_all)]
+async fn disputes(
+    State(AppState { db, .. }): State<AppState>,
+    Query(query): Query<DisputeQuery>,
+) -> Result<Json<Vec<DisputeRecord>>, Error> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    Ok(Json(db.open_disputes(limit.into()).await?))
+}
+
+pub fn router(auth: Arc<InboundAuth>) -> axum::Router<AppState> {
+    axum::Router::new()
+        .route("/transactions", get(search))
+        .route("/transactions/{reference}", get(details))
+        .route("/transactions/{reference}/resolve", post(resolve))
+        .route("/discrepancies", get(discrepancies))
+        .route("/disputes", get(disputes))
+        .route("/export", get(export))
+        .route(
+            "/settlements",
+            post(reconcile).layer(DefaultBodyLimit::max(MAX_REPORT_SIZE)),
+        )
+        .route_layer(axum::middleware::from_fn_with_state(auth, authenticate))
+}
@@ -0,0 +1,183 @@
+use std::{io::Read, path::Path, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+/// Table that ships with the binary. Can be replaced with `BIN_TABLE_PATH`
+const EMBEDDED_TABLE: &str = include_str!("bin_ranges.csv");
+
+static BIN_TABLE: LazyLock<BinTable> = LazyLock::new(|| {
+    let Ok(path) = std::env::var("BIN_TABLE_PATH") else {
+        return BinTable::from_csv(EMBEDDED_TABLE.as_bytes()).expect("embedded bin table is valid");
+    };
+    let table = BinTable::from_path(&path).expect("bin table configuration is valid");
+    tracing::info!(%path, ranges = table.ranges.len(), "Loaded BIN table");
+    table
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CardType {
+    Debit,
+    Credit,
+    Prepaid,
+}
+
+impl CardType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CardType::Debit => "DEBIT",
+            CardType::Credit => "CREDIT",
+            CardType::Prepaid => "PREPAID",
+        }
+    }
+}
+
+/// Card details derived from the leading digits of the PAN
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BinInfo {
+    /// Card scheme (e.g. VISA, MASTERCARD, VERVE)
+    pub scheme: String,
+    pub card_type: Option<CardType>,
+    /// ISO 3166-1 alpha-2 code of the issuing country
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BinRow {
+    start: String,
+    end: String,
+    scheme: String,
+    card_type: Option<CardType>,
+    country: Option<String>,
+}
+
+#[derive(Debug)]
+struct BinRange {
+    /// Amount of leading PAN digits this range is matched against
+    len: usize,
+    start: u64,
+    end: u64,
+    info: BinInfo,
+}
+
+#[derive(Debug)]
+pub struct BinTable {
+    /// Ranges ordered from the most specific (longest prefix) to the least specific one
+    ranges: Vec<BinRange>,
+}
+
+impl BinTable {
+    /// Parse the table from csv with `start,end,scheme,card_type,country` header.
+    ///
+    /// `start` and `end` are inclusive PAN prefixes of the same length.
+    pub fn from_csv(reader: impl Read) -> anyhow::Result<Self> {
+        let mut ranges = Vec::new();
+        for (i, row) in csv::Reader::from_reader(reader)
+            .deserialize::<BinRow>()
+            .enumerate()
+        {
+            let row = row?;
+            let line = i + 2;
+            if row.start.len() != row.end.len() {
+                anyhow::bail!("line {line}: range bounds must have the same length");
+            }
+            let (Ok(start), Ok(end)) = (row.start.parse(), row.end.parse()) else {
+                anyhow::bail!("line {line}: range bounds must be numeric");
+            };
+            if start > end {
+                anyhow::bail!("line {line}: range start is greater than its end");
+            }
+            ranges.push(BinRange {
+                len: row.start.len(),
+                start,
+                end,
+                info: BinInfo {
+                    scheme: row.scheme.to_uppercase(),
+                    card_type: row.card_type,
+                    country: row.country.map(|c| c.to_uppercase()),
+                },
+            });
+        }
+        // Stable sort keeps file order between ranges of the same length
+        ranges.sort_by_key(|range| std::cmp::Reverse(range.len));
+        Ok(Self { ranges })
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_csv(std::fs::File::open(path)?)
+    }
+
+    /// Table configured for this process
+    pub fn global() -> &'static Self {
+        &BIN_TABLE
+    }
+
+    /// Find the most specific range that matches the PAN
+    pub fn lookup(&self, pan: &str) -> Option<&BinInfo> {
+        if !pan.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        self.ranges
+            .iter()
+            .find(|range| {
+                pan.get(..range.len)
+                    .and_then(|prefix| prefix.parse::<u64>().ok())
+                    .is_some_and(|prefix| (range.start..=range.end).contains(&prefix))
+            })
+            .map(|range| &range.info)
+    }
+}
+
+/// Lookup card details in the process wide table
+pub fn lookup(pan: &str) -> Option<&'static BinInfo> {
+    BinTable::global().lookup(pan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinTable, CardType, EMBEDDED_TABLE};
+
+    fn embedded() -> BinTable {
+        BinTable::from_csv(EMBEDDED_TABLE.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn lookup_schemes() {
+        let table = embedded();
+        assert_eq!(table.lookup("4485081333091151").unwrap().scheme, "VISA");
+        assert_eq!(
+            table.lookup("5178831496700363").unwrap().scheme,
+            "MASTERCARD"
+        );
+        assert_eq!(
+            table.lookup("2223000048400011").unwrap().scheme,
+            "MASTERCARD"
+        );
+        assert_eq!(table.lookup("378282246310005").unwrap().scheme, "AMEX");
+        assert!(table.lookup("9999999999999999").is_none());
+        assert!(table.lookup("4485-0813").is_none());
+    }
+
+    #[test]
+    fn most_specific_range_wins() {
+        let table = embedded();
+        let verve = table.lookup("6500020000000000000").unwrap();
+        assert_eq!(verve.scheme, "VERVE");
+        assert_eq!(verve.card_type, Some(CardType::Debit));
+        assert_eq!(verve.country.as_deref(), Some("NG"));
+        assert_eq!(table.lookup("6500280000000000").unwrap().scheme, "DISCOVER");
+    }
+
+    #[test]
+    fn custom_table() {
+        let csv = "start,end,scheme,card_type,country\n4,4,visa,,\n448508,448508,visa,PREPAID,gb\n";
+        let table = BinTable::from_csv(csv.as_bytes()).unwrap();
+        let info = table.lookup("4485081333091151").unwrap();
+        assert_eq!(info.card_type, Some(CardType::Prepaid));
+        assert_eq!(info.country.as_deref(), Some("GB"));
+        assert_eq!(table.lookup("4111111111111111").unwrap().card_type, None);
+
+        let invalid = "start,end,scheme,card_type,country\n51,5,MASTERCARD,,\n";
+        assert!(BinTable::from_csv(invalid.as_bytes()).is_err());
+    }
+}
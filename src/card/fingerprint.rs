@@ -0,0 +1,75 @@
+use std::sync::LazyLock;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::keyring::{KeyEncoding, KeyRing};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key of card fingerprints. Changing it splits payments made before and after by the same card
+pub static FINGERPRINT_KEYS: LazyLock<Option<KeyRing>> = LazyLock::new(|| {
+    KeyRing::from_env("FINGERPRINT_KEY", KeyEncoding::Hex)
+        .expect("fingerprint key configuration is valid")
+});
+
+/// Card identity stored with the transaction instead of the PAN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardFingerprint {
+    /// Hex encoded HMAC-SHA256 of the PAN, `None` when `FINGERPRINT_KEY` is not configured
+    pub fingerprint: Option<String>,
+    /// First 6 digits of the PAN
+    pub bin: String,
+    pub last4: String,
+}
+
+impl CardFingerprint {
+    /// Fingerprint of the card with the configured key
+    pub fn new(pan: &str) -> Self {
+        Self::with_key(FINGERPRINT_KEYS.as_ref().map(|keys| keys.active().1), pan)
+    }
+
+    pub fn with_key(key: Option<&[u8; 32]>, pan: &str) -> Self {
+        let fingerprint = key.map(|key| {
+            let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+            mac.update(pan.as_bytes());
+            hex::encode(mac.finalize().into_bytes())
+        });
+        Self {
+            fingerprint,
+            bin: pan.get(..6).unwrap_or(pan).to_string(),
+            last4: pan
+                .get(pan.len().saturating_sub(4)..)
+                .unwrap_or(pan)
+                .to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CardFingerprint;
+
+    #[test]
+    fn fingerprint() {
+        let key = [7; 32];
+        let card = CardFingerprint::with_key(Some(&key), "4485081333091151");
+        assert_eq!(card.bin, "448508");
+        assert_eq!(card.last4, "1151");
+        let fingerprint = card.fingerprint.unwrap();
+        assert_eq!(fingerprint.len(), 64);
+        assert!(!fingerprint.contains("4485081333091151"));
+
+        // Same card links, other card or key does not
+        let same = CardFingerprint::with_key(Some(&key), "4485081333091151");
+        assert_eq!(same.fingerprint.as_deref(), Some(fingerprint.as_str()));
+        let other = CardFingerprint::with_key(Some(&key), "4485081333091169");
+        assert_ne!(other.fingerprint.as_deref(), Some(fingerprint.as_str()));
+        let rekeyed = CardFingerprint::with_key(Some(&[8; 32]), "4485081333091151");
+        assert_ne!(rekeyed.fingerprint.as_deref(), Some(fingerprint.as_str()));
+
+        let unkeyed = CardFingerprint::with_key(None, "4485081333091151");
+        assert!(unkeyed.fingerprint.is_none());
+        assert_eq!(unkeyed.last4, "1151");
+    }
+}
@@ -0,0 +1,6 @@
+/// Offline BIN range table
+pub mod bin;
+/// Keyed card fingerprints that link payments without the PAN
+pub mod fingerprint;
+/// Local encrypted card storage
+pub mod vault;
@@ -0,0 +1,124 @@
+use std::sync::LazyLock;
+
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{
+    connect::api::payment::H2HCardParams,
+    db::Db,
+    keyring::{KeyEncoding, KeyRing},
+    secret::Secrets,
+};
+
+/// Purpose of the data key derived from the vault key
+const VAULT_KEY_INFO: &[u8] = b"segura-gateway/card-vault";
+
+/// Vault configured with `VAULT_KEY` or `VAULT_KEYS`, cards are not stored without it
+pub static VAULT: LazyLock<Option<Vault>> = LazyLock::new(|| {
+    let keys =
+        KeyRing::from_env("VAULT_KEY", KeyEncoding::Hex).expect("vault key configuration is valid");
+    keys.as_ref().map(Vault::new)
+});
+
+/// Card as it is sealed, CVV is never stored
+#[derive(Serialize, Deserialize, ZeroizeOnDrop)]
+struct StoredCard {
+    pan: String,
+    expires: String,
+    holder: String,
+}
+
+/// Local card storage. PAN is encrypted with the dedicated vault key, which is separate from
+/// `DB_MASTER_KEY`, and the card is referenced by an opaque token
+pub struct Vault {
+    keys: Secrets,
+}
+
+impl Vault {
+    pub fn new(keys: &KeyRing) -> Self {
+        Self {
+            keys: Secrets::with_purpose(Some(keys), VAULT_KEY_INFO),
+        }
+    }
+
+    /// Store the card used for the payment, returns its token
+    pub async fn store(
+        &self,
+        db: &Db,
+        card: &H2HCardParams,
+        gateway_reference: &str,
+    ) -> anyhow::Result<String> {
+        let stored = StoredCard {
+            pan: card.pan.clone(),
+            expires: card.expires.clone(),
+            holder: card.holder.clone(),
+        };
+        let mut plaintext = serde_json::to_string(&stored)?;
+        let sealed = self.keys.seal(&plaintext);
+        plaintext.zeroize();
+        let token = uuid::Uuid::new_v4().to_string();
+        db.insert_vault_card(&token, &sealed, gateway_reference)
+            .await?;
+        Ok(token)
+    }
+
+    /// Card by its token, without CVV
+    pub async fn load(&self, db: &Db, token: &str) -> anyhow::Result<Option<H2HCardParams>> {
+        let Some(sealed) = db.vault_card(token).await? else {
+            return Ok(None);
+        };
+        let mut plaintext = self.keys.open(&sealed)?;
+        let stored = serde_json::from_str::<StoredCard>(&plaintext);
+        plaintext.zeroize();
+        let mut stored = stored?;
+        Ok(Some(H2HCardParams {
+            cvv: String::new(),
+            expires: std::mem::take(&mut stored.expires),
+            pan: std::mem::take(&mut stored.pan),
+            holder: std::mem::take(&mut stored.holder),
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::Vault;
+    use crate::{
+        connect::api::payment::H2HCardParams,
+        db::tests::sqlite,
+        keyring::{KeyEncoding, KeyRing},
+    };
+
+    fn with_key(key: &str) -> Vault {
+        let keys = KeyRing::parse(None, Some(key), None, KeyEncoding::Hex)
+            .unwrap()
+            .unwrap();
+        Vault::new(&keys)
+    }
+
+    #[tokio::test]
+    async fn store_and_load() {
+        let db = sqlite().await;
+        let vault = with_key("0909090909090909090909090909090909090909090909090909090909090909");
+        let card = H2HCardParams {
+            cvv: "123".into(),
+            expires: "11/2029".into(),
+            pan: "4485081333091151".into(),
+            holder: "Test testov".into(),
+        };
+        let token = vault.store(&db, &card, "reference").await.unwrap();
+        let sealed = db.vault_card(&token).await.unwrap().unwrap();
+        assert!(!sealed.contains("4485081333091151"));
+
+        let loaded = vault.load(&db, &token).await.unwrap().unwrap();
+        assert_eq!(loaded.pan, card.pan);
+        assert_eq!(loaded.expires, card.expires);
+        assert_eq!(loaded.holder, card.holder);
+        assert!(loaded.cvv.is_empty());
+        assert!(vault.load(&db, "unknown").await.unwrap().is_none());
+
+        // Cards are opened only with the vault key they were sealed with
+        let other = with_key("0707070707070707070707070707070707070707070707070707070707070707");
+        assert!(other.load(&db, &token).await.is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use futures_util::TryStreamExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    db::{Db, TransactionFilter},
+    export,
+    jobs::retention,
+    settlement,
+};
+
+const USAGE: &str = "usage:
+    segura-gateway [serve]
+    segura-gateway reencrypt-secrets
+    segura-gateway purge
+    segura-gateway export --output <path> [--format csv|ndjson] [--client-id <id>] [--from <time>] [--to <time>]
+    segura-gateway reconcile --input <path> [--output <path>] [--correct] [--from <time> --to <time>]
+
+<time> is either unix timestamp or RFC 3339 date time";
+
+/// Maintenance command that runs instead of the server
+#[derive(Debug)]
+pub enum Command {
+    /// Encrypt plaintext secrets and secrets sealed with older master keys with the active one
+    ReencryptSecrets,
+    /// Purge data that is older than the retention policy allows
+    Purge,
+    /// Write transactions to the file
+    Export {
+        output: PathBuf,
+        format: export::Format,
+        filter: TransactionFilter,
+    },
+    /// Compare settlement report with stored transactions
+    Reconcile {
+        input: PathBuf,
+        /// Mismatches are written here as CSV
+        output: Option<PathBuf>,
+        options: settlement::Options,
+    },
+}
+
+/// Options without a value
+const FLAGS: [&str; 1] = ["correct"];
+
+impl Command {
+    /// Parse process arguments, `None` means the server should be started
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let name = args.next();
+        let mut options = parse_options(args)?;
+        let command = match name.as_deref() {
+            None | Some("serve") => None,
+            Some("reencrypt-secrets") => Some(Self::ReencryptSecrets),
+            Some("purge") => Some(Self::Purge),
+            Some("export") => {
+                let output = options
+                    .remove("output")
+                    .ok_or_else(|| anyhow::anyhow!("--output is required\n{USAGE}"))?;
+                let format = match options.remove("format") {
+                    Some(format) => format.parse()?,
+                    None => export::Format::default(),
+                };
+                let filter = TransactionFilter {
+                    client_id: options.remove("client-id"),
+                    created_from: options
+                        .remove("from")
+                        .as_deref()
+                        .map(parse_time)
+                        .transpose()?,
+                    created_to: options
+                        .remove("to")
+                        .as_deref()
+                        .map(parse_time)
+                        .transpose()?,
+                    ..Default::default()
+                };
+                Some(Self::Export {
+                    output: output.into(),
+                    format,
+                    filter,
+                })
+            }
+            Some("reconcile") => {
+                let input = options
+                    .remove("input")
+                    .ok_or_else(|| anyhow::anyhow!("--input is required\n{USAGE}"))?;
+                let from = options
+                    .remove("from")
+                    .as_deref()
+                    .map(parse_time)
+                    .transpose()?;
+                let to = options
+                    .remove("to")
+                    .as_deref()
+                    .map(parse_time)
+                    .transpose()?;
+                let period = match (from, to) {
+                    (Some(from), Some(to)) => Some((from, to)),
+                    (None, None) => None,
+                    _ => anyhow::bail!("--from and --to must be used together\n{USAGE}"),
+                };
+                Some(Self::Reconcile {
+                    input: input.into(),
+                    output: options.remove("output").map(Into::into),
+                    options: settlement::Options {
+                        correct: options.remove("correct").is_some(),
+                        period,
+                    },
+                })
+            }
+            Some(other) => anyhow::bail!("unknown command {other}\n{USAGE}"),
+        };
+        if let Some(extra) = options.keys().next() {
+            anyhow::bail!("unexpected option --{extra}\n{USAGE}");
+        }
+        Ok(command)
+    }
+
+    pub async fn run(self, db: Db) -> anyhow::Result<()> {
+        match self {
+            Command::ReencryptSecrets => {
+                let encrypted = db.reencrypt_secrets().await?;
+                tracing::info!(encrypted, "Finished re-encrypting secrets");
+            }
+            Command::Purge => {
+                let config = retention::Config::from_env()?;
+                if config.is_disabled() {
+                    anyhow::bail!(
+                        "none of RETENTION_PII_DAYS, RETENTION_MAPPING_DAYS or RETENTION_CARD_DAYS is defined"
+                    );
+                }
+                retention::purge(&db, &config).await?;
+            }
+            Command::Export {
+                output,
+                format,
+                filter,
+            } => {
+                let mut file = tokio::fs::File::create(&output).await?;
+                let mut chunks = std::pin::pin!(export::transactions(db, filter, format));
+                let mut bytes = 0;
+                while let Some(chunk) = chunks.try_next().await? {
+                    file.write_all(&chunk).await?;
+                    bytes += chunk.len();
+                }
+                file.flush().await?;
+                tracing::info!(output = %output.display(), bytes, "Exported transactions");
+            }
+            Command::Reconcile {
+                input,
+                output,
+                options,
+            } => {
+                let report = std::fs::File::open(&input)?;
+                let report = settlement::reconcile(&db, report, &options).await?;
+                match output {
+                    Some(output) => settlement::write_csv(&report, std::fs::File::create(output)?)?,
+                    None => settlement::write_csv(&report, std::io::stdout())?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collect `--name value` pairs and [FLAGS] (with empty value)
+fn parse_options(
+    mut args: impl Iterator<Item = String>,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut options = BTreeMap::new();
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix("--") else {
+            anyhow::bail!("unexpected argument {arg}\n{USAGE}");
+        };
+        if FLAGS.contains(&name) {
+            options.insert(name.to_string(), String::new());
+            continue;
+        }
+        let Some(value) = args.next() else {
+            anyhow::bail!("--{name} requires a value\n{USAGE}");
+        };
+        options.insert(name.to_string(), value);
+    }
+    Ok(options)
+}
+
+/// Unix timestamp or RFC 3339 date time
+fn parse_time(value: &str) -> anyhow::Result<i64> {
+    if let Ok(ts) = value.parse() {
+        return Ok(ts);
+    }
+    let datetime =
+        time::OffsetDateTime::parse(value, &time::format_description::well_known::Rfc3339)
+            .map_err(|e| anyhow::anyhow!("invalid time {value}: {e}"))?;
+    Ok(datetime.unix_timestamp())
+}
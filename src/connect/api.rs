@@ -1,51 +1,99 @@
+use std::{collections::BTreeMap, sync::Arc};
+
 use axum::{extract::State, response::IntoResponse, routing::post};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::{
+    card::{fingerprint::CardFingerprint, vault::VAULT},
     connect::{
         GwConnectErrorResponse, Result,
+        auth::{InboundAuth, authenticate},
+        capture,
         interaction_log::{InteractionLog, InteractionSpan},
         status,
     },
-    gateway,
+    db::{CardSource, Db, NewCardToken, NewDiscrepancy, NewTransaction, Transaction},
+    gateway::{self, SeguraStatus, StatusEffect, sync::notify},
+    money::Money,
+    risk,
     state::AppState,
 };
 
 #[instrument(skip_all)]
 pub async fn pay(
-    State(AppState { db }): State<AppState>,
-    Json(payment): Json<payment::GwConnectH2HPaymentRequest>,
+    State(AppState { db, .. }): State<AppState>,
+    Json(mut payment): Json<payment::GwConnectH2HPaymentRequest>,
 ) -> Result<GwConnectResponse<GwConnectH2HPaymentResponse>> {
-    let mut span = InteractionSpan::enter();
     let ctx = gateway::RequestContext::new(&payment.settings);
-    match &payment.params.card_params {
-        Some(card_params) => match ctx.init_h2h_payment((&payment).into(), &mut span).await {
+    // Card is taken out of the request so it is zeroized right after the payment is processed
+    let card_params = payment.params.card_params.take();
+    let card = card_params
+        .as_ref()
+        .map(|card_params| CardFingerprint::new(&card_params.pan));
+    let (assessment, risk_log) = assess_risk(&db, &payment, card.as_ref()).await?;
+    let mut span = InteractionSpan::enter();
+    let init_request = gateway::payin::PaymentInitRequest::from(&payment);
+    let client_reference = init_request.client_reference.clone();
+    match card_params {
+        Some(card_params) => match ctx.init_h2h_payment(init_request, &mut span).await {
             Ok(init_response) => {
                 let init_log = span.interaction_log("init_payment");
+                let reference = init_response.data.reference;
+                if let Err(e) =
+                    store_transaction(&db, &payment, &client_reference, &reference, card.as_ref())
+                        .await
+                    && gateway::returns_through_gateway()
+                {
+                    return Err(GwConnectErrorResponse::new(
+                        e.to_string(),
+                        vec![risk_log, init_log],
+                    ));
+                }
+                record_risk(&db, &reference, &assessment, &risk_log).await;
+                store_log(&db, &reference, &init_log).await;
                 let mut process_span = InteractionSpan::enter();
-                match ctx
+                let res = ctx
                     .process_h2h_payment(
                         &payment,
-                        card_params,
-                        db,
-                        &init_response.data.reference,
+                        &card_params,
+                        db.clone(),
+                        &reference,
                         &mut process_span,
                     )
-                    .await
-                {
+                    .await;
+                match res {
                     Ok(res) => {
                         let process_log = process_span.interaction_log("payment");
+                        store_log(&db, &reference, &process_log).await;
+                        let card_token = res.data.card_token().map(str::to_string);
+                        let mut response = complete_payment(&db, &payment, res, &reference).await;
+                        // Declined card is never saved
+                        if payment.params.save_card.unwrap_or(false)
+                            && response.result != super::Status::Declined
+                        {
+                            response.card_token = save_card(
+                                &db,
+                                &payment,
+                                &reference,
+                                &card_params,
+                                card_token.as_deref(),
+                            )
+                            .await;
+                        }
+                        drop(card_params);
                         Ok(GwConnectResponse::<GwConnectH2HPaymentResponse>::new(
-                            (res, init_response.data.reference).into(),
-                            vec![init_log, process_log],
+                            response,
+                            vec![risk_log, init_log, process_log],
                         ))
                     }
                     Err(e) => {
+                        drop(card_params);
                         let process_log = process_span.interaction_log("payment");
+                        store_log(&db, &reference, &process_log).await;
                         Err(GwConnectErrorResponse::new(
                             e.to_string(),
-                            vec![init_log, process_log],
+                            vec![risk_log, init_log, process_log],
                         ))
                     }
                 }
@@ -53,28 +101,375 @@ pub async fn pay(
             Err(e) => {
                 tracing::error!("Failed to init h2h payment: {e}");
                 let log = span.interaction_log("payment");
-                Err(GwConnectErrorResponse::new(e.to_string(), vec![log]))
+                Err(GwConnectErrorResponse::new(
+                    e.to_string(),
+                    vec![risk_log, log],
+                ))
             }
         },
-        None => match ctx.hosted_payment(payment, &mut span).await {
+        None => match ctx.hosted_payment(init_request, &mut span).await {
             Ok(res) => {
                 span.set_response(&res);
                 let log = span.interaction_log("payment");
                 tracing::info!(code = res.code, "Created payment");
+                let reference = &res.data.reference;
+                if let Err(e) =
+                    store_transaction(&db, &payment, &client_reference, reference, None).await
+                    && gateway::returns_through_gateway()
+                {
+                    return Err(GwConnectErrorResponse::new(
+                        e.to_string(),
+                        vec![risk_log, log],
+                    ));
+                }
+                record_risk(&db, reference, &assessment, &risk_log).await;
+                store_log(&db, reference, &log).await;
+                if let Err(e) = db
+                    .insert_mapping(
+                        &payment.payment.merchant_private_key,
+                        &payment.payment.token,
+                        reference,
+                    )
+                    .await
+                {
+                    tracing::error!("Failed to insert gateway id mapping: {e}");
+                }
                 Ok(GwConnectResponse::<GwConnectH2HPaymentResponse>::new(
                     res.into(),
-                    vec![log],
+                    vec![risk_log, log],
                 ))
             }
             Err(e) => {
                 tracing::error!("Failed to create a payment: {e}");
                 let log = span.interaction_log("payment");
-                Err(GwConnectErrorResponse::new(e.to_string(), vec![log]))
+                Err(GwConnectErrorResponse::new(
+                    e.to_string(),
+                    vec![risk_log, log],
+                ))
             }
         },
     }
 }
 
+/// Evaluate risk rules before the payment reaches the gateway, declined payment is answered with
+/// the error right away
+async fn assess_risk(
+    db: &Db,
+    payment: &payment::GwConnectH2HPaymentRequest,
+    card: Option<&CardFingerprint>,
+) -> Result<(risk::Assessment, InteractionLog)> {
+    let money = payment.payment.money();
+    let input = risk::RiskInput {
+        client_id: &payment.settings.client_id,
+        money: &money,
+        country: payment.params.country.as_deref(),
+        email: payment.params.email.as_deref(),
+        ip: payment.payment.ip.as_deref(),
+        card,
+    };
+    let mut span = InteractionSpan::enter();
+    let assessment = risk::RiskRules::global().assess(db, &input).await;
+    span.set_response(&assessment);
+    let log = span.interaction_log("risk");
+    if assessment.decision == risk::Decision::Decline {
+        tracing::warn!(rules = ?assessment.rules, "Payment is declined by risk rules");
+        return Err(GwConnectErrorResponse::new(
+            "payment is declined by risk rules".to_string(),
+            vec![log],
+        ));
+    }
+    Ok((assessment, log))
+}
+
+/// Store the risk decision with the transaction, payment under review is recorded as a
+/// discrepancy for support
+async fn record_risk(
+    db: &Db,
+    gateway_reference: &str,
+    assessment: &risk::Assessment,
+    log: &InteractionLog,
+) {
+    store_log(db, gateway_reference, log).await;
+    if assessment.decision != risk::Decision::Review {
+        return;
+    }
+    let details = assessment.rules.join(", ");
+    let discrepancy = NewDiscrepancy {
+        gateway_reference,
+        kind: "risk_review",
+        our_status: None,
+        gateway_status: None,
+        details: Some(&details),
+        fixed: false,
+    };
+    if let Err(e) = db.insert_discrepancy(discrepancy).await {
+        tracing::error!("Failed to record risk review: {e}");
+    }
+}
+
+/// Response for the processed payment, its result is stored right away unless it is pending
+async fn complete_payment(
+    db: &Db,
+    payment: &payment::GwConnectH2HPaymentRequest,
+    res: gateway::SeguraOkResponse<gateway::payin::PaymentProcessData>,
+    reference: &str,
+) -> GwConnectH2HPaymentResponse {
+    let mut response = GwConnectH2HPaymentResponse::from((res, reference.to_string()));
+    if payment.auth_only() && response.result == super::Status::Approved {
+        response.result = super::Status::Authorized;
+    }
+    if response.result != super::Status::Pending
+        && let Err(e) = db
+            .finalize_transaction(reference, response.result, None)
+            .await
+    {
+        tracing::error!("Failed to update transaction status: {e}");
+    }
+    response
+}
+
+/// Save the card in the vault, or the gateway card token without the vault, returns the token
+/// for Gateway.Connect. Card can't be charged until the payment is approved
+async fn save_card(
+    db: &Db,
+    payment: &payment::GwConnectH2HPaymentRequest,
+    reference: &str,
+    card_params: &payment::H2HCardParams,
+    gateway_card_token: Option<&str>,
+) -> Option<String> {
+    let vault_token = match VAULT.as_ref() {
+        Some(vault) => vault
+            .store(db, card_params, reference)
+            .await
+            .inspect_err(|e| tracing::error!("Failed to store card in the vault: {e}"))
+            .ok(),
+        None => None,
+    };
+    let tokenized = match &vault_token {
+        Some(token) => Some((token.as_str(), CardSource::Vault)),
+        None => gateway_card_token.map(|token| (token, CardSource::Gateway)),
+    };
+    let Some((gateway_card_token, source)) = tokenized else {
+        tracing::warn!("Card is not tokenized, it is not saved");
+        return None;
+    };
+    let card = NewCardToken {
+        client_id: &payment.settings.client_id,
+        gateway_reference: reference,
+        gateway_card_token,
+        source,
+    };
+    match db.insert_card_token(card).await {
+        Ok(token) => Some(token),
+        Err(e) => {
+            tracing::error!("Failed to save card token: {e}");
+            // Vaulted card nobody can charge is not kept
+            if let Some(token) = &vault_token
+                && let Err(e) = db.delete_vault_card(token).await
+            {
+                tracing::error!("Failed to delete vault card: {e}");
+            }
+            None
+        }
+    }
+}
+
+/// Charge the card saved by an earlier payment without CVV
+#[instrument(skip_all)]
+pub async fn recurring(
+    State(AppState { db, .. }): State<AppState>,
+    Json(request): Json<payment::RecurringRequest>,
+) -> Result<GwConnectResponse<GwConnectH2HPaymentResponse>> {
+    let payment = &request.payment;
+    let card = match db
+        .usable_card_token(&request.card_token, &payment.settings.client_id)
+        .await
+    {
+        Ok(Some(card)) => card,
+        Ok(None) => {
+            tracing::warn!("Card token is not found, revoked or not approved yet");
+            return Err(GwConnectErrorResponse::new(
+                "card token is not found".to_string(),
+                vec![],
+            ));
+        }
+        Err(e) => {
+            tracing::error!("Failed to retrieve card token from the database: {e}");
+            return Err(GwConnectErrorResponse::new(e.to_string(), vec![]));
+        }
+    };
+    let vault_card = match card.source {
+        CardSource::Gateway => None,
+        CardSource::Vault => {
+            let loaded = match VAULT.as_ref() {
+                Some(vault) => vault.load(&db, &card.gateway_card_token).await,
+                None => Err(anyhow::anyhow!("card vault is not configured")),
+            };
+            match loaded {
+                Ok(Some(vault_card)) => Some(vault_card),
+                Ok(None) => {
+                    tracing::warn!("Saved card is missing from the vault");
+                    return Err(GwConnectErrorResponse::new(
+                        "card token is not found".to_string(),
+                        vec![],
+                    ));
+                }
+                Err(e) => {
+                    tracing::error!("Failed to load card from the vault: {e}");
+                    return Err(GwConnectErrorResponse::new(e.to_string(), vec![]));
+                }
+            }
+        }
+    };
+    let saved_card = card.card();
+    let (assessment, risk_log) = assess_risk(&db, payment, saved_card.as_ref()).await?;
+    let mut span = InteractionSpan::enter();
+    let ctx = gateway::RequestContext::new(&payment.settings);
+    let init_request = gateway::payin::PaymentInitRequest::from(payment);
+    let client_reference = init_request.client_reference.clone();
+    let init_response = ctx.init_h2h_payment(init_request, &mut span).await;
+    let init_log = span.interaction_log("init_payment");
+    let reference = match init_response {
+        Ok(init_response) => init_response.data.reference,
+        Err(e) => {
+            tracing::error!("Failed to init recurring payment: {e}");
+            return Err(GwConnectErrorResponse::new(
+                e.to_string(),
+                vec![risk_log, init_log],
+            ));
+        }
+    };
+    if let Err(e) = store_transaction(
+        &db,
+        payment,
+        &client_reference,
+        &reference,
+        saved_card.as_ref(),
+    )
+    .await
+        && gateway::returns_through_gateway()
+    {
+        return Err(GwConnectErrorResponse::new(
+            e.to_string(),
+            vec![risk_log, init_log],
+        ));
+    }
+    record_risk(&db, &reference, &assessment, &risk_log).await;
+    store_log(&db, &reference, &init_log).await;
+    if let Err(e) = db
+        .insert_mapping(
+            &payment.payment.merchant_private_key,
+            &payment.payment.token,
+            &reference,
+        )
+        .await
+    {
+        tracing::error!("Failed to insert gateway id mapping: {e}");
+    }
+    let mut process_span = InteractionSpan::enter();
+    let res = match &vault_card {
+        Some(vault_card) => {
+            ctx.process_stored_card(payment, vault_card, &reference, &mut process_span)
+                .await
+        }
+        None => {
+            ctx.recurring_payment(&reference, &card.gateway_card_token, &mut process_span)
+                .await
+        }
+    };
+    drop(vault_card);
+    let process_log = process_span.interaction_log("recurring_payment");
+    store_log(&db, &reference, &process_log).await;
+    match res {
+        Ok(res) => {
+            let response = complete_payment(&db, payment, res, &reference).await;
+            tracing::info!(first_payment = %card.gateway_reference, "Charged saved card");
+            Ok(GwConnectResponse::new(
+                response,
+                vec![risk_log, init_log, process_log],
+            ))
+        }
+        Err(e) => {
+            tracing::error!("Failed to process recurring payment: {e}");
+            Err(GwConnectErrorResponse::new(
+                e.to_string(),
+                vec![risk_log, init_log, process_log],
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CardRevocationResult {
+    pub card_token: String,
+}
+
+/// Revoke the saved card, it can't be charged anymore
+#[instrument(skip_all)]
+pub async fn revoke_card(
+    State(AppState { db, .. }): State<AppState>,
+    Json(request): Json<payment::CardRevocationRequest>,
+) -> Result<GwConnectResponse<CardRevocationResult>> {
+    match db
+        .revoke_card_token(&request.card_token, &request.settings.client_id)
+        .await
+    {
+        Ok(false) => Err(GwConnectErrorResponse::new(
+            "card token is not found".to_string(),
+            vec![],
+        )),
+        Ok(true) => {
+            tracing::info!("Revoked card token");
+            Ok(GwConnectResponse::new(
+                CardRevocationResult {
+                    card_token: request.card_token,
+                },
+                vec![],
+            ))
+        }
+        Err(e) => {
+            tracing::error!("Failed to revoke card token: {e}");
+            Err(GwConnectErrorResponse::new(e.to_string(), vec![]))
+        }
+    }
+}
+
+/// Persist initialized transaction. Failure is fatal only when the customer returns through
+/// the gateway return route, which looks the transaction up
+async fn store_transaction(
+    db: &Db,
+    payment: &payment::GwConnectH2HPaymentRequest,
+    client_reference: &str,
+    gateway_reference: &str,
+    card: Option<&CardFingerprint>,
+) -> sqlx::Result<()> {
+    let money = payment.payment.money();
+    let transaction = NewTransaction {
+        token: &payment.payment.token,
+        client_reference,
+        gateway_reference,
+        processing_url: &payment.processing_url,
+        amount: money.amount,
+        currency: &money.currency,
+        settings: &payment.settings,
+        customer_email: payment.params.email.as_deref(),
+        customer_ip: payment.payment.ip.as_deref(),
+        customer_country: payment.params.country.as_deref(),
+        auth_only: payment.auth_only(),
+        card,
+    };
+    db.insert_transaction(transaction)
+        .await
+        .inspect_err(|e| tracing::error!("Failed to store transaction: {e}"))
+}
+
+/// Persist interaction log of the transaction for support. Failure is not fatal as well
+async fn store_log(db: &Db, gateway_reference: &str, log: &InteractionLog) {
+    if let Err(e) = db.insert_interaction_log(gateway_reference, log).await {
+        tracing::error!("Failed to store interaction log: {e}");
+    }
+}
+
 #[instrument(skip_all)]
 pub async fn status(
     Json(status_request): Json<status::req::Request>,
@@ -85,12 +480,14 @@ pub async fn status(
     match ctx
         .status(&status_request.payment.gateway_token, &mut span)
         .await
+        .map_err(anyhow::Error::from)
+        .and_then(status::res::Status::try_from)
     {
         Ok(status) => {
             let log = span.interaction_log("status");
             tracing::info!(id = %status_request.payment.token, "Dispatched transaction status");
             Ok(GwConnectResponse::<status::res::Status>::new(
-                status.into(),
+                status,
                 vec![log],
             ))
         }
@@ -102,6 +499,153 @@ pub async fn status(
     }
 }
 
+/// Capture the authorized payment, fully or partially. Transaction becomes approved with the
+/// captured amount
+#[instrument(skip_all, fields(gateway_token = %request.payment.gateway_token))]
+pub async fn capture(
+    State(AppState { db, .. }): State<AppState>,
+    Json(request): Json<capture::req::Request>,
+) -> Result<GwConnectResponse<capture::res::Operation>> {
+    let mut transaction = authorized_transaction(&db, &request.payment).await?;
+    let authorized = transaction.money();
+    let amount = match request.payment.amount {
+        Some(amount) => Money::new(amount as i64, &authorized.currency),
+        None => authorized.clone(),
+    };
+    if amount.amount <= 0 || amount.amount > authorized.amount {
+        return Err(GwConnectErrorResponse::new(
+            format!(
+                "capture amount must be positive and at most {}",
+                authorized.amount
+            ),
+            vec![],
+        ));
+    }
+    let ctx = gateway::RequestContext::new(&request.settings);
+    let mut span = InteractionSpan::enter();
+    let response = ctx
+        .capture(&transaction.gateway_reference, &amount, &mut span)
+        .await;
+    let log = span.interaction_log("capture");
+    store_log(&db, &transaction.gateway_reference, &log).await;
+    let status = match response {
+        Ok(response) => response.data.status,
+        Err(e) => {
+            tracing::error!("Failed to capture payment: {e}");
+            return Err(GwConnectErrorResponse::new(e.to_string(), vec![log]));
+        }
+    };
+    if status.effect() != StatusEffect::Final(super::Status::Approved) {
+        tracing::warn!(status = status.as_str(), "Payment is not captured");
+        return Err(GwConnectErrorResponse::new(
+            format!(
+                "payment is not captured, gateway status {}",
+                status.as_str()
+            ),
+            vec![log],
+        ));
+    }
+    match db
+        .capture_transaction(&transaction.gateway_reference, amount.amount)
+        .await
+    {
+        Ok(true) => {
+            transaction.status = super::Status::Approved;
+            transaction.captured_amount = Some(amount.amount);
+            notify_operation(&db, &transaction, None).await;
+        }
+        Ok(false) => tracing::warn!("Transaction was captured or cancelled meanwhile"),
+        Err(e) => tracing::error!("Failed to update transaction status: {e}"),
+    }
+    tracing::info!(amount = amount.amount, "Captured payment");
+    Ok(GwConnectResponse::new(
+        capture::res::Operation {
+            status: super::Status::Approved,
+            amount,
+        },
+        vec![log],
+    ))
+}
+
+/// Cancel the authorized payment, its funds are released. Transaction becomes declined
+#[instrument(skip_all, fields(gateway_token = %request.payment.gateway_token))]
+pub async fn cancel(
+    State(AppState { db, .. }): State<AppState>,
+    Json(request): Json<capture::req::Request>,
+) -> Result<GwConnectResponse<capture::res::Operation>> {
+    let mut transaction = authorized_transaction(&db, &request.payment).await?;
+    let ctx = gateway::RequestContext::new(&request.settings);
+    let mut span = InteractionSpan::enter();
+    let response = ctx.void(&transaction.gateway_reference, &mut span).await;
+    let log = span.interaction_log("cancel");
+    store_log(&db, &transaction.gateway_reference, &log).await;
+    let status = match response {
+        Ok(response) => response.data.status,
+        Err(e) => {
+            tracing::error!("Failed to cancel payment: {e}");
+            return Err(GwConnectErrorResponse::new(e.to_string(), vec![log]));
+        }
+    };
+    if !matches!(status, SeguraStatus::Success | SeguraStatus::Reversed) {
+        tracing::warn!(status = status.as_str(), "Payment is not cancelled");
+        return Err(GwConnectErrorResponse::new(
+            format!(
+                "payment is not cancelled, gateway status {}",
+                status.as_str()
+            ),
+            vec![log],
+        ));
+    }
+    let details = "Authorization cancelled";
+    match db
+        .cancel_transaction(&transaction.gateway_reference, details)
+        .await
+    {
+        Ok(true) => {
+            transaction.status = super::Status::Declined;
+            notify_operation(&db, &transaction, Some(details.to_string())).await;
+        }
+        Ok(false) => tracing::warn!("Transaction was captured or cancelled meanwhile"),
+        Err(e) => tracing::error!("Failed to update transaction status: {e}"),
+    }
+    tracing::info!("Cancelled payment");
+    Ok(GwConnectResponse::new(
+        capture::res::Operation {
+            status: super::Status::Declined,
+            amount: transaction.money(),
+        },
+        vec![log],
+    ))
+}
+
+/// Transaction of the capture or cancel request, it has to be authorized
+async fn authorized_transaction(db: &Db, payment: &capture::req::Payment) -> Result<Transaction> {
+    let error = |e: String| GwConnectErrorResponse::new(e, vec![]);
+    let transaction = db
+        .get_transaction_by_gateway_reference(&payment.gateway_token)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to retrieve transaction from the database: {e}");
+            error(e.to_string())
+        })?
+        .filter(|transaction| transaction.token == payment.token)
+        .ok_or_else(|| error("payment is not found".to_string()))?;
+    if transaction.status != super::Status::Authorized {
+        return Err(error(format!(
+            "payment is {}, only authorized payments can be captured or cancelled",
+            transaction.status.as_str()
+        )));
+    }
+    Ok(transaction)
+}
+
+/// Callbacks follow the transaction status, failure to queue one is not fatal for the operation
+async fn notify_operation(db: &Db, transaction: &Transaction, details: Option<String>) {
+    if let Err(e) = notify(db, transaction, transaction.status, details).await {
+        tracing::error!("Failed to queue callback for gateway.connect: {e}");
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct GwConnectResponse<T> {
     result: bool,
@@ -132,6 +676,12 @@ impl<T: Serialize> IntoResponse for GwConnectResponse<T> {
 pub mod payment {
 
     use serde::Deserialize;
+    use zeroize::{Zeroize, ZeroizeOnDrop};
+
+    use crate::{
+        gateway::mask::{MaskPolicy, Masked},
+        money::Money,
+    };
 
     #[derive(Debug, Deserialize, Clone)]
     pub struct GwConnectH2HPaymentRequest {
@@ -141,6 +691,16 @@ pub mod payment {
         pub settings: Settings,
     }
 
+    impl GwConnectH2HPaymentRequest {
+        /// Payment is only authorized, it is captured or cancelled later
+        pub fn auth_only(&self) -> bool {
+            self.params
+                .auth_only
+                .or(self.settings.auth_only)
+                .unwrap_or(false)
+        }
+    }
+
     #[derive(Debug, Deserialize, Clone)]
     pub struct H2HParams {
         #[serde(flatten)]
@@ -155,9 +715,14 @@ pub mod payment {
         pub email: Option<String>,
         pub country: Option<String>,
         pub state: Option<String>,
+        /// Overrides [Settings::auth_only] for the payment
+        pub auth_only: Option<bool>,
+        /// Save the card to charge it later with `/recurring`
+        pub save_card: Option<bool>,
     }
 
-    #[derive(Debug, Clone)]
+    /// Raw card data, buffers are zeroized on drop
+    #[derive(Clone, Zeroize, ZeroizeOnDrop)]
     pub struct H2HCardParams {
         pub cvv: String,
         pub expires: String,
@@ -165,6 +730,14 @@ pub mod payment {
         pub holder: String,
     }
 
+    impl std::fmt::Debug for H2HCardParams {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("H2HCardParams")
+                .field("pan", &Masked::mask(&self.pan))
+                .finish_non_exhaustive()
+        }
+    }
+
     // Implement deserialize manually to conceal any "helpful" error messages that can leak
     // sensitive data
     impl<'de> serde::de::Deserialize<'de> for H2HCardParams {
@@ -172,7 +745,7 @@ pub mod payment {
         where
             D: serde::de::Deserializer<'de>,
         {
-            #[derive(Debug, Clone, Deserialize)]
+            #[derive(Deserialize, ZeroizeOnDrop)]
             struct H2HCardParamsShadow {
                 cvv: String,
                 expires: String,
@@ -181,21 +754,14 @@ pub mod payment {
             }
 
             impl From<H2HCardParamsShadow> for H2HCardParams {
-                fn from(
-                    H2HCardParamsShadow {
-                        cvv,
-                        expires,
-                        pan,
-                        holder,
-                    }: H2HCardParamsShadow,
-                ) -> Self {
+                fn from(mut shadow: H2HCardParamsShadow) -> Self {
                     // Replace '-' when recativepay sends us incorrect card pan.
-                    let pan = pan.replace('-', "");
+                    let pan = shadow.pan.replace('-', "");
                     Self {
-                        cvv,
-                        expires,
+                        cvv: std::mem::take(&mut shadow.cvv),
+                        expires: std::mem::take(&mut shadow.expires),
                         pan,
-                        holder,
+                        holder: std::mem::take(&mut shadow.holder),
                     }
                 }
             }
@@ -208,6 +774,7 @@ pub mod payment {
 
     #[derive(Debug, Deserialize, Clone)]
     pub struct Payment {
+        /// Amount in minor units
         pub gateway_amount: usize,
         pub gateway_currency: String,
         pub product: String,
@@ -217,11 +784,33 @@ pub mod payment {
         pub merchant_private_key: String,
     }
 
+    impl Payment {
+        pub fn money(&self) -> Money {
+            Money::new(self.gateway_amount as i64, &self.gateway_currency)
+        }
+    }
+
+    /// Payment with the card saved by an earlier one
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct RecurringRequest {
+        #[serde(flatten)]
+        pub payment: GwConnectH2HPaymentRequest,
+        pub card_token: String,
+    }
+
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct CardRevocationRequest {
+        pub card_token: String,
+        pub settings: Settings,
+    }
+
     #[derive(Debug, Deserialize, Clone)]
     pub struct Settings {
         pub client_id: String,
         pub secret: String,
         pub sandbox: Option<bool>,
+        /// Authorize payments and capture them later with `/capture`
+        pub auth_only: Option<bool>,
     }
 }
 
@@ -232,6 +821,9 @@ pub struct GwConnectH2HPaymentResponse {
     pub result: super::Status,
     pub card_enrolled: bool,
     pub gateway_token: Option<String>,
+    /// Saved card to charge with `/recurring`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub card_token: Option<String>,
 }
 
 #[derive(Debug, Serialize, Default)]
@@ -239,6 +831,12 @@ pub struct RedirectRequest {
     pub url: String,
     #[serde(rename = "type")]
     pub kind: RedirectRequestType,
+    /// Form fields for the `post` and `post_iframes` redirect types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<BTreeMap<String, String>>,
+    /// Rendered page for the `redirect_html` redirect type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
 }
 
 #[derive(Debug, Serialize, Default)]
@@ -253,10 +851,50 @@ pub enum RedirectRequestType {
     RedirectHtml,
 }
 
-pub fn router() -> axum::Router<crate::state::AppState> {
+#[derive(Debug, Deserialize)]
+pub struct ErasureRequest {
+    /// Payment token
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErasureResult {
+    /// Amount of transactions customer data was removed from
+    pub erased: u64,
+}
+
+/// Remove customer data of the payment on the data subject request
+#[instrument(skip_all)]
+pub async fn erase(
+    State(AppState { db, .. }): State<AppState>,
+    Json(request): Json<ErasureRequest>,
+) -> Result<GwConnectResponse<ErasureResult>> {
+    match db.erase_customer_data(&request.token).await {
+        Ok(0) => Err(GwConnectErrorResponse::new(
+            "payment is not found".to_string(),
+            vec![],
+        )),
+        Ok(erased) => {
+            tracing::info!(token = %request.token, erased, "Erased customer data");
+            Ok(GwConnectResponse::new(ErasureResult { erased }, vec![]))
+        }
+        Err(e) => {
+            tracing::error!(token = %request.token, "Failed to erase customer data: {e}");
+            Err(GwConnectErrorResponse::new(e.to_string(), vec![]))
+        }
+    }
+}
+
+pub fn router(auth: Arc<InboundAuth>) -> axum::Router<crate::state::AppState> {
     axum::Router::new()
         .route("/pay", post(pay))
         .route("/status", post(status))
+        .route("/capture", post(capture))
+        .route("/cancel", post(cancel))
+        .route("/recurring", post(recurring))
+        .route("/cards/revoke", post(revoke_card))
+        .route("/erase", post(erase))
+        .route_layer(axum::middleware::from_fn_with_state(auth, authenticate))
 }
 
 /// `Json` extractor wrapper that customizes the error from `axum::extract::Json`
@@ -0,0 +1,278 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::connect::GwConnectErrorResponse;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TIMESTAMP_HEADER: &str = "x-timestamp";
+const SIGNATURE_HEADER: &str = "x-signature";
+const DEFAULT_SIGNATURE_TOLERANCE: i64 = 300;
+/// Signed bodies are buffered in memory, keep them reasonably small
+const MAX_SIGNED_BODY: usize = 2 * 1024 * 1024;
+
+/// Inbound authentication of internal API callers (Connect API, admin API).
+///
+/// Every configured method must pass, nothing is checked when none of them are configured.
+#[derive(Debug, Default)]
+pub struct InboundAuth {
+    /// Shared bearer token
+    bearer_token: Option<String>,
+    /// Secret for `X-Signature: hex(hmac_sha256("{X-Timestamp}.{body}"))`
+    hmac_secret: Option<Vec<u8>>,
+    /// Maximum allowed difference between `X-Timestamp` and our clock in seconds
+    signature_tolerance: i64,
+    /// Networks allowed to call the API, empty list allows everyone
+    allowed_networks: Vec<ipnet::IpNet>,
+}
+
+#[derive(Debug)]
+enum Rejection {
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    TooLarge(&'static str),
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        let (status, reason) = match self {
+            Rejection::Unauthorized(reason) => (StatusCode::UNAUTHORIZED, reason),
+            Rejection::Forbidden(reason) => (StatusCode::FORBIDDEN, reason),
+            Rejection::TooLarge(reason) => (StatusCode::PAYLOAD_TOO_LARGE, reason),
+        };
+        tracing::warn!(%status, "Rejected inbound request: {reason}");
+        (
+            status,
+            axum::Json(GwConnectErrorResponse::new(reason.to_string(), vec![])),
+        )
+            .into_response()
+    }
+}
+
+impl InboundAuth {
+    /// Read configuration from `{prefix}_AUTH_TOKEN`, `{prefix}_HMAC_SECRET`,
+    /// `{prefix}_HMAC_TOLERANCE` and `{prefix}_ALLOWED_IPS` env variables
+    pub fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let non_empty = |name: &str| {
+            std::env::var(format!("{prefix}_{name}"))
+                .ok()
+                .filter(|v| !v.is_empty())
+        };
+        let signature_tolerance = match non_empty("HMAC_TOLERANCE") {
+            Some(v) => v.parse()?,
+            None => DEFAULT_SIGNATURE_TOLERANCE,
+        };
+        let allowed_networks = match non_empty("ALLOWED_IPS") {
+            Some(v) => parse_networks(&v)?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            bearer_token: non_empty("AUTH_TOKEN"),
+            hmac_secret: non_empty("HMAC_SECRET").map(String::into_bytes),
+            signature_tolerance,
+            allowed_networks,
+        })
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.bearer_token.is_none()
+            && self.hmac_secret.is_none()
+            && self.allowed_networks.is_empty()
+    }
+
+    fn check_ip(&self, ip: Option<IpAddr>) -> Result<(), Rejection> {
+        if self.allowed_networks.is_empty() {
+            return Ok(());
+        }
+        let ip = ip.ok_or(Rejection::Forbidden("peer address is unknown"))?;
+        // Ipv4 peers of the dual stack sockets are reported as mapped ipv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match self.allowed_networks.iter().any(|net| net.contains(&ip)) {
+            true => Ok(()),
+            false => Err(Rejection::Forbidden("address is not allowed")),
+        }
+    }
+
+    fn check_token(&self, headers: &HeaderMap) -> Result<(), Rejection> {
+        let Some(expected) = &self.bearer_token else {
+            return Ok(());
+        };
+        let token = headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or(Rejection::Unauthorized("missing bearer token"))?;
+        match bool::from(token.as_bytes().ct_eq(expected.as_bytes())) {
+            true => Ok(()),
+            false => Err(Rejection::Unauthorized("invalid bearer token")),
+        }
+    }
+
+    fn check_signature(&self, headers: &HeaderMap, body: &[u8], now: i64) -> Result<(), Rejection> {
+        let Some(secret) = &self.hmac_secret else {
+            return Ok(());
+        };
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        let timestamp =
+            header(TIMESTAMP_HEADER).ok_or(Rejection::Unauthorized("missing request timestamp"))?;
+        let signature =
+            header(SIGNATURE_HEADER).ok_or(Rejection::Unauthorized("missing request signature"))?;
+        let ts: i64 = timestamp
+            .parse()
+            .map_err(|_| Rejection::Unauthorized("invalid request timestamp"))?;
+        if (now - ts).abs() > self.signature_tolerance {
+            return Err(Rejection::Unauthorized(
+                "request timestamp is out of tolerance",
+            ));
+        }
+        let signature = hex::decode(signature)
+            .map_err(|_| Rejection::Unauthorized("invalid request signature"))?;
+        let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts any key length");
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        mac.verify_slice(&signature)
+            .map_err(|_| Rejection::Unauthorized("invalid request signature"))
+    }
+}
+
+fn parse_networks(list: &str) -> anyhow::Result<Vec<ipnet::IpNet>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| match v.parse::<ipnet::IpNet>() {
+            Ok(net) => Ok(net),
+            Err(_) => Ok(v.parse::<IpAddr>()?.into()),
+        })
+        .collect()
+}
+
+/// Middleware that rejects requests failing any of the configured checks
+pub async fn authenticate(
+    State(auth): State<Arc<InboundAuth>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if auth.is_disabled() {
+        return next.run(request).await;
+    }
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Err(rejection) = auth
+        .check_ip(ip)
+        .and_then(|_| auth.check_token(request.headers()))
+    {
+        return rejection.into_response();
+    }
+
+    let request = if auth.hmac_secret.is_some() {
+        let (parts, body) = request.into_parts();
+        let Ok(bytes) = axum::body::to_bytes(body, MAX_SIGNED_BODY).await else {
+            return Rejection::TooLarge("signed request body is too large").into_response();
+        };
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        if let Err(rejection) = auth.check_signature(&parts.headers, &bytes, now) {
+            return rejection.into_response();
+        }
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        request
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+    use hmac::Mac;
+
+    use super::{HmacSha256, InboundAuth, parse_networks};
+
+    fn sign(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+        mac.update(format!("{timestamp}.").as_bytes());
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn bearer_token() {
+        let auth = InboundAuth {
+            bearer_token: Some("secret-token".into()),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert!(auth.check_token(&headers).is_err());
+        headers.insert("authorization", HeaderValue::from_static("Bearer wrong"));
+        assert!(auth.check_token(&headers).is_err());
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("Bearer secret-token"),
+        );
+        assert!(auth.check_token(&headers).is_ok());
+    }
+
+    #[test]
+    fn body_signature() {
+        let secret = b"hmac-secret";
+        let auth = InboundAuth {
+            hmac_secret: Some(secret.to_vec()),
+            signature_tolerance: 300,
+            ..Default::default()
+        };
+        let body = br#"{"payment":{}}"#;
+        let now = 1_763_000_000;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-timestamp", now.to_string().parse().unwrap());
+        headers.insert(
+            "x-signature",
+            sign(secret, &now.to_string(), body).parse().unwrap(),
+        );
+        assert!(auth.check_signature(&headers, body, now + 10).is_ok());
+        // Stale timestamp
+        assert!(auth.check_signature(&headers, body, now + 301).is_err());
+        // Tampered body
+        assert!(auth.check_signature(&headers, b"{}", now).is_err());
+        headers.remove("x-signature");
+        assert!(auth.check_signature(&headers, body, now).is_err());
+    }
+
+    #[test]
+    fn ip_allowlist() {
+        let auth = InboundAuth {
+            allowed_networks: parse_networks("10.0.0.0/8, 192.168.1.10,::1").unwrap(),
+            ..Default::default()
+        };
+        assert!(auth.check_ip(Some("10.1.2.3".parse().unwrap())).is_ok());
+        assert!(auth.check_ip(Some("192.168.1.10".parse().unwrap())).is_ok());
+        assert!(
+            auth.check_ip(Some("::ffff:10.0.0.1".parse().unwrap()))
+                .is_ok()
+        );
+        assert!(auth.check_ip(Some("::1".parse().unwrap())).is_ok());
+        assert!(
+            auth.check_ip(Some("192.168.1.11".parse().unwrap()))
+                .is_err()
+        );
+        assert!(auth.check_ip(None).is_err());
+        assert!(parse_networks("10.0.0.0/33").is_err());
+    }
+}
@@ -42,6 +42,7 @@ fn encrypt_merchant_key(
 pub fn create_jwt(
     payload: &CallbackPayload,
     merchant_key: &str,
+    key_id: &str,
     sign_key: &[u8; 32],
 ) -> anyhow::Result<String> {
     let iv = gen_iv();
@@ -57,7 +58,7 @@ pub fn create_jwt(
         },
     };
 
-    let token = j::encode(&payload, sign_key)?;
+    let token = j::encode(&payload, key_id, sign_key)?;
     Ok(token)
 }
 
@@ -71,22 +72,29 @@ mod j {
     type HmacSha512 = Hmac<Sha512>;
 
     #[derive(Debug, Serialize)]
-    struct Header {
+    struct Header<'a> {
         alg: &'static str,
         typ: &'static str,
+        /// Id of the key used for signature and merchant key encryption
+        kid: &'a str,
     }
 
-    impl Header {
-        fn sha512() -> Self {
+    impl<'a> Header<'a> {
+        fn sha512(kid: &'a str) -> Self {
             Self {
                 alg: "HS512",
                 typ: "JWT",
+                kid,
             }
         }
     }
 
-    pub fn encode(payload: impl Serialize, sign_key: &[u8; 32]) -> anyhow::Result<String> {
-        let header = Header::sha512();
+    pub fn encode(
+        payload: impl Serialize,
+        key_id: &str,
+        sign_key: &[u8; 32],
+    ) -> anyhow::Result<String> {
+        let header = Header::sha512(key_id);
         let payload = payload;
         let mut result = String::new();
         result.push_str(&BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_string(&header)?));
@@ -103,7 +111,7 @@ mod j {
 
 #[cfg(test)]
 mod tests {
-    use base64::{Engine, engine::general_purpose};
+    use base64::{Engine, engine::general_purpose, prelude::BASE64_URL_SAFE_NO_PAD};
 
     use crate::connect::callback::{CallbackStatus, jwt::encrypt_merchant_key};
 
@@ -112,13 +120,24 @@ mod tests {
         let merchant_key = "zlfhcrecevingrxrlsbezrepunag";
         let payload = super::CallbackPayload {
             status: CallbackStatus::Approved,
-            currency: "RUB".into(),
-            amount: 100,
+            amount: crate::money::Money::new(100, "RUB"),
         };
-        super::create_jwt(&payload, merchant_key, b"vhfrnepuogjvhfrarbivzogjvhfrehfg").unwrap();
+        let jwt = super::create_jwt(
+            &payload,
+            merchant_key,
+            "2026",
+            b"vhfrnepuogjvhfrarbivzogjvhfrehfg",
+        )
+        .unwrap();
+        let header = jwt.split('.').next().unwrap();
+        let header: serde_json::Value =
+            serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap();
+        assert_eq!(header["kid"], "2026");
+        assert_eq!(header["alg"], "HS512");
     }
 
     #[test]
+    #[allow(clippy::useless_conversion, clippy::needless_borrows_for_generic_args)]
     fn encrypt_private_key() {
         let merchant_key = "5178831496700b3634e4";
         let sign_key: [u8; 32] = TryFrom::try_from(*b"e7403b3c0d76a35312e7cc65eeb75808").unwrap();
@@ -1,57 +1,165 @@
-use std::time::Duration;
+use std::sync::LazyLock;
 
 use axum::http::HeaderMap;
 use axum_extra::headers::HeaderMapExt;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{Db, NewCallback},
+    keyring::{KeyEncoding, KeyRing},
+    money::Money,
+};
 
 pub mod jwt;
 
-const RETRY_ATTEMPTS: usize = 3;
-const RETRY_DELAY: Duration = Duration::from_secs(3);
+/// Keys shared with Gateway.Connect. Callbacks are signed with the active one and carry its id
+static SIGN_KEYS: LazyLock<KeyRing> = LazyLock::new(|| {
+    KeyRing::from_env("SIGN_KEY", KeyEncoding::Raw)
+        .expect("sign key configuration is valid")
+        .expect("SIGN_KEY or SIGN_KEYS env is defined")
+});
 
 #[derive(Debug)]
 pub struct SendArguments {
     pub merchant_key: String,
     pub token: String,
-    pub currency: String,
     pub status: CallbackStatus,
-    pub amount: usize,
+    pub amount: Money,
 }
 
 #[derive(Serialize)]
 pub struct CallbackPayload {
     #[serde(flatten)]
     pub status: CallbackStatus,
-    pub currency: String,
-    pub amount: usize,
+    /// Amount in minor units and currency
+    #[serde(flatten)]
+    pub amount: Money,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "lowercase", tag = "status")]
 pub enum CallbackStatus {
-    Declined { reason: String },
+    Declined {
+        reason: String,
+    },
     Approved,
+    /// Funds are held, the payment waits for capture or cancel
+    Authorized,
+    /// Dispute raised against the approved payment and its updates
+    Chargeback {
+        reason: String,
+        dispute_reference: String,
+        dispute_status: DisputeStatus,
+    },
+}
+
+impl From<&CallbackStatus> for super::Status {
+    fn from(value: &CallbackStatus) -> Self {
+        match value {
+            CallbackStatus::Declined { .. } => Self::Declined,
+            // Chargeback does not change the outcome of the payment
+            CallbackStatus::Approved | CallbackStatus::Chargeback { .. } => Self::Approved,
+            CallbackStatus::Authorized => Self::Authorized,
+        }
+    }
+}
+
+impl CallbackStatus {
+    /// Callback for the transaction status, `reason` is sent for declined ones
+    pub fn new(status: super::Status, reason: Option<String>) -> Self {
+        match status {
+            super::Status::Approved => Self::Approved,
+            super::Status::Authorized => Self::Authorized,
+            _ => Self::Declined {
+                reason: reason.unwrap_or_default(),
+            },
+        }
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputeStatus {
+    Open,
+    /// Resolved in favour of the merchant
+    Won,
+    /// Resolved in favour of the cardholder, money is returned
+    Lost,
+}
+
+impl DisputeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Won => "won",
+            Self::Lost => "lost",
+        }
+    }
+}
+
+impl std::str::FromStr for DisputeStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(Self::Open),
+            "won" => Ok(Self::Won),
+            "lost" => Ok(Self::Lost),
+            _ => Err(anyhow::anyhow!("unknown dispute status {s}")),
+        }
+    }
+}
+
+/// Send the callback and record its outcome for the transaction
 pub async fn send_callback(
+    db: &Db,
+    gateway_reference: &str,
+    args: SendArguments,
+) -> anyhow::Result<()> {
+    let token = args.token.clone();
+    let amount = args.amount.clone();
+    let status = super::Status::from(&args.status);
+    let reason = match &args.status {
+        CallbackStatus::Declined { reason } | CallbackStatus::Chargeback { reason, .. } => {
+            Some(reason.clone())
+        }
+        CallbackStatus::Approved | CallbackStatus::Authorized => None,
+    };
+    let res = deliver(args).await;
+    let error = res.as_ref().err().map(ToString::to_string);
+    let callback = NewCallback {
+        gateway_reference,
+        token: &token,
+        status,
+        reason: reason.as_deref(),
+        amount: amount.amount,
+        currency: &amount.currency,
+        delivered: res.is_ok(),
+        error: error.as_deref(),
+    };
+    if let Err(e) = db.insert_callback(callback).await {
+        tracing::error!("Failed to record sent callback: {e}");
+    }
+    res
+}
+
+/// Send the dispute notification. Disputes keep their delivery state themselves, so nothing is
+/// recorded for the transaction
+pub async fn send_dispute(args: SendArguments) -> anyhow::Result<()> {
+    deliver(args).await
+}
+
+async fn deliver(
     SendArguments {
         merchant_key,
         token,
-        currency,
         status,
         amount,
     }: SendArguments,
 ) -> anyhow::Result<()> {
-    let sign_key = std::env::var("SIGN_KEY").expect("SIGN_KEY env is defined");
-    assert_eq!(sign_key.len(), 32);
-    let key: [u8; 32] = sign_key.as_bytes().try_into().expect("length is 32 bytes");
-
-    let payload = CallbackPayload {
-        status,
-        currency,
-        amount,
-    };
-    let jwt = jwt::create_jwt(&payload, &merchant_key, &key)?;
+    let (key_id, key) = SIGN_KEYS.active();
+    let payload = CallbackPayload { status, amount };
+    let jwt = jwt::create_jwt(&payload, &merchant_key, key_id, key)?;
     let client = reqwest::Client::new();
     let mut headers = HeaderMap::new();
     headers.typed_insert(axum_extra::headers::Authorization::bearer(&jwt).unwrap());
@@ -60,26 +168,14 @@ pub async fn send_callback(
         "http://business:4000".to_string()
     });
 
-    for i in 0..RETRY_ATTEMPTS {
-        match client
-            .post(format!("{base}/callbacks/v2/gateway_callbacks/{token}"))
-            .headers(headers.clone())
-            .json(&payload)
-            .send()
-            .await
-            .and_then(|e| e.error_for_status())
-        {
-            Ok(_) => return Ok(()),
-            Err(e) => {
-                tracing::error!(
-                    attempt = i + 1,
-                    "Failed to send callback to gateway connect: {e}"
-                );
-                if i + 1 < RETRY_ATTEMPTS {
-                    tokio::time::sleep(RETRY_DELAY).await;
-                }
-            }
-        }
-    }
-    Err(anyhow::anyhow!("max attempts exceeded"))
+    // Single attempt, failed callbacks are retried by the outbox job
+    client
+        .post(format!("{base}/callbacks/v2/gateway_callbacks/{token}"))
+        .headers(headers)
+        .json(&payload)
+        .send()
+        .await
+        .and_then(|e| e.error_for_status())
+        .inspect_err(|e| tracing::error!("Failed to send callback to gateway connect: {e}"))?;
+    Ok(())
 }
@@ -0,0 +1,36 @@
+pub mod req {
+    use serde::Deserialize;
+
+    use crate::connect::api::payment::Settings;
+
+    /// Capture or cancel of the authorized payment
+    #[derive(Debug, Deserialize)]
+    pub struct Request {
+        pub payment: Payment,
+        pub settings: Settings,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Payment {
+        pub gateway_token: String,
+        pub token: String,
+        /// Amount to capture in minor units, the whole authorized amount by default. Not used
+        /// when cancelling
+        pub amount: Option<usize>,
+    }
+}
+
+pub mod res {
+    use serde::Serialize;
+
+    use crate::{connect, money::Money};
+
+    #[derive(Debug, Serialize)]
+    pub struct Operation {
+        /// Transaction status after the operation, approved or declined
+        pub status: connect::Status,
+        /// Captured amount in minor units and currency, authorized one when cancelled
+        #[serde(flatten)]
+        pub amount: Money,
+    }
+}
@@ -29,6 +29,12 @@ pub struct InteractionSpan {
     response_status: Option<u16>,
 }
 
+impl InteractionLog {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+}
+
 impl InteractionSpan {
     pub fn enter() -> Self {
         Self {
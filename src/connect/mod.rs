@@ -1,20 +1,26 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::gateway::mask;
 
 pub mod api;
+/// Inbound authentication of the Connect API
+pub mod auth;
 pub mod callback;
+/// Capture and cancel of authorized payments
+pub mod capture;
 pub mod interaction_log;
 pub mod status;
 
 pub type Result<T> = std::result::Result<T, GwConnectErrorResponse>;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Status {
     Approved,
     Declined,
     Pending,
+    /// Funds of the auth-only payment are held until it is captured or cancelled
+    Authorized,
 }
 
 #[derive(Debug, Serialize)]
@@ -24,6 +30,35 @@ pub struct GwConnectErrorResponse {
     logs: Vec<interaction_log::InteractionLog>,
 }
 
+impl Status {
+    pub fn is_final(&self) -> bool {
+        !matches!(self, Status::Pending | Status::Authorized)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Approved => "approved",
+            Status::Declined => "declined",
+            Status::Pending => "pending",
+            Status::Authorized => "authorized",
+        }
+    }
+}
+
+impl std::str::FromStr for Status {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "approved" => Ok(Status::Approved),
+            "declined" => Ok(Status::Declined),
+            "pending" => Ok(Status::Pending),
+            "authorized" => Ok(Status::Authorized),
+            _ => Err(anyhow::anyhow!("unknown transaction status {s}")),
+        }
+    }
+}
+
 impl std::error::Error for GwConnectErrorResponse {}
 
 impl std::fmt::Display for GwConnectErrorResponse {
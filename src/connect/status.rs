@@ -19,7 +19,7 @@ pub mod req {
 pub mod res {
     use serde::Serialize;
 
-    use crate::connect;
+    use crate::{connect, money::Money};
 
     #[derive(Debug, Serialize)]
     pub struct Status {
@@ -27,8 +27,8 @@ pub mod res {
         pub status: connect::Status,
         /// Additional status details or message
         pub details: String,
-        /// Amount of the transaction in minor units
-        pub amount: usize,
-        pub currency: String,
+        /// Amount of the transaction in minor units and currency
+        #[serde(flatten)]
+        pub amount: Money,
     }
 }
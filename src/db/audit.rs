@@ -0,0 +1,266 @@
+use serde::Serialize;
+
+use crate::{
+    connect::{self, interaction_log::InteractionLog},
+    gateway::mask,
+};
+
+use super::{Db, now};
+
+#[derive(Debug)]
+pub struct NewCallback<'a> {
+    pub gateway_reference: &'a str,
+    pub token: &'a str,
+    pub status: connect::Status,
+    pub reason: Option<&'a str>,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: &'a str,
+    pub delivered: bool,
+    pub error: Option<&'a str>,
+}
+
+/// Callback sent to Gateway.Connect
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct CallbackRecord {
+    pub token: String,
+    pub status: connect::Status,
+    pub reason: Option<String>,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: String,
+    pub delivered: bool,
+    pub error: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug)]
+pub struct NewReceivedCallback<'a> {
+    pub gateway_reference: &'a str,
+    /// Status as the gateway names it
+    pub status: &'a str,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: &'a str,
+    pub description: &'a str,
+    pub outcome: &'a str,
+}
+
+/// Callback received from the gateway
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ReceivedCallbackRecord {
+    pub status: String,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: String,
+    pub description: String,
+    pub outcome: String,
+    pub created_at: i64,
+}
+
+/// Request to the gateway made for the transaction
+#[derive(Debug, Serialize)]
+pub struct InteractionLogRecord {
+    pub kind: String,
+    pub log: serde_json::Value,
+    pub created_at: i64,
+}
+
+#[derive(Debug)]
+pub struct NewDiscrepancy<'a> {
+    pub gateway_reference: &'a str,
+    pub kind: &'a str,
+    pub our_status: Option<connect::Status>,
+    pub gateway_status: Option<connect::Status>,
+    pub details: Option<&'a str>,
+    /// Discrepancy was resolved automatically
+    pub fixed: bool,
+}
+
+/// Difference between the stored transaction and the gateway found by reconciliation
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct DiscrepancyRecord {
+    pub gateway_reference: String,
+    pub kind: String,
+    pub our_status: Option<connect::Status>,
+    pub gateway_status: Option<connect::Status>,
+    pub details: Option<String>,
+    pub fixed: bool,
+    pub created_at: i64,
+}
+
+impl Db {
+    /// Store the interaction log, card data is masked
+    pub async fn insert_interaction_log(
+        &self,
+        gateway_reference: &str,
+        log: &InteractionLog,
+    ) -> sqlx::Result<()> {
+        let value = mask::secure_serializable(log);
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "INSERT INTO interaction_logs (gateway_reference, kind, log, created_at) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(gateway_reference)
+            .bind(log.kind())
+            .bind(value.to_string())
+            .bind(now())
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(())
+    }
+
+    pub async fn interaction_logs(
+        &self,
+        gateway_reference: &str,
+    ) -> sqlx::Result<Vec<InteractionLogRecord>> {
+        let rows: Vec<(String, String, i64)> = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "SELECT kind, log, created_at FROM interaction_logs WHERE gateway_reference = $1 ORDER BY id",
+            )
+            .bind(gateway_reference)
+            .fetch_all(pool)
+            .await?
+        });
+        rows.into_iter()
+            .map(|(kind, log, created_at)| {
+                Ok(InteractionLogRecord {
+                    kind,
+                    log: serde_json::from_str(&log).map_err(|e| sqlx::Error::Decode(e.into()))?,
+                    created_at,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn insert_callback(&self, callback: NewCallback<'_>) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "INSERT INTO callbacks (gateway_reference, token, status, reason, amount, currency, delivered, error, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            )
+            .bind(callback.gateway_reference)
+            .bind(callback.token)
+            .bind(callback.status)
+            .bind(callback.reason)
+            .bind(callback.amount)
+            .bind(callback.currency)
+            .bind(callback.delivered)
+            .bind(callback.error)
+            .bind(now())
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(())
+    }
+
+    pub async fn callbacks(&self, gateway_reference: &str) -> sqlx::Result<Vec<CallbackRecord>> {
+        let callbacks = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "SELECT token, status, reason, amount, currency, delivered, error, created_at FROM callbacks
+                WHERE gateway_reference = $1 ORDER BY id",
+            )
+            .bind(gateway_reference)
+            .fetch_all(pool)
+            .await?
+        });
+        Ok(callbacks)
+    }
+
+    pub async fn insert_received_callback(
+        &self,
+        callback: NewReceivedCallback<'_>,
+    ) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "INSERT INTO received_callbacks (gateway_reference, status, amount, currency, description, outcome, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(callback.gateway_reference)
+            .bind(callback.status)
+            .bind(callback.amount)
+            .bind(callback.currency)
+            .bind(callback.description)
+            .bind(callback.outcome)
+            .bind(now())
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(())
+    }
+
+    pub async fn received_callbacks(
+        &self,
+        gateway_reference: &str,
+    ) -> sqlx::Result<Vec<ReceivedCallbackRecord>> {
+        let callbacks = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "SELECT status, amount, currency, description, outcome, created_at FROM received_callbacks
+                WHERE gateway_reference = $1 ORDER BY id",
+            )
+            .bind(gateway_reference)
+            .fetch_all(pool)
+            .await?
+        });
+        Ok(callbacks)
+    }
+
+    /// Status of the last callback sent for the transaction and whether it was delivered
+    pub async fn last_callback(
+        &self,
+        gateway_reference: &str,
+    ) -> sqlx::Result<Option<(connect::Status, bool)>> {
+        let callback = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "SELECT status, delivered FROM callbacks WHERE gateway_reference = $1 ORDER BY id DESC LIMIT 1",
+            )
+            .bind(gateway_reference)
+            .fetch_optional(pool)
+            .await?
+        });
+        Ok(callback)
+    }
+
+    pub async fn insert_discrepancy(&self, discrepancy: NewDiscrepancy<'_>) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "INSERT INTO discrepancies (gateway_reference, kind, our_status, gateway_status, details, fixed, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(discrepancy.gateway_reference)
+            .bind(discrepancy.kind)
+            .bind(discrepancy.our_status)
+            .bind(discrepancy.gateway_status)
+            .bind(discrepancy.details)
+            .bind(discrepancy.fixed)
+            .bind(now())
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(())
+    }
+
+    /// Discrepancies found since the timestamp, newest first
+    pub async fn discrepancies(
+        &self,
+        since: i64,
+        limit: i64,
+    ) -> sqlx::Result<Vec<DiscrepancyRecord>> {
+        let discrepancies = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "SELECT gateway_reference, kind, our_status, gateway_status, details, fixed, created_at FROM discrepancies
+                WHERE created_at >= $1 ORDER BY id DESC LIMIT $2",
+            )
+            .bind(since)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?
+        });
+        Ok(discrepancies)
+    }
+}
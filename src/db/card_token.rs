@@ -0,0 +1,141 @@
+use crate::{card::fingerprint::CardFingerprint, connect};
+
+use super::{Db, now};
+
+#[derive(Debug)]
+pub struct NewCardToken<'a> {
+    /// Merchant gateway account the card is saved for
+    pub client_id: &'a str,
+    /// First payment with the card
+    pub gateway_reference: &'a str,
+    /// Gateway card token or vault token, depending on `source`
+    pub gateway_card_token: &'a str,
+    pub source: CardSource,
+}
+
+/// Where the saved card is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardSource {
+    /// Gateway tokenized the card, it is charged with `/recurring`
+    Gateway,
+    /// Card is in the local vault, it is charged with `/process` without CVV
+    Vault,
+}
+
+impl CardSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gateway => "gateway",
+            Self::Vault => "vault",
+        }
+    }
+}
+
+impl std::str::FromStr for CardSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gateway" => Ok(Self::Gateway),
+            "vault" => Ok(Self::Vault),
+            _ => Err(anyhow::anyhow!("unknown card source {s}")),
+        }
+    }
+}
+
+/// Saved card that can be charged
+#[derive(Debug, sqlx::FromRow)]
+pub struct CardToken {
+    pub gateway_card_token: String,
+    /// First payment with the card
+    pub gateway_reference: String,
+    pub source: CardSource,
+    card_fingerprint: Option<String>,
+    card_bin: Option<String>,
+    card_last4: Option<String>,
+}
+
+impl CardToken {
+    /// Card of the first payment
+    pub fn card(&self) -> Option<CardFingerprint> {
+        Some(CardFingerprint {
+            fingerprint: self.card_fingerprint.clone(),
+            bin: self.card_bin.clone()?,
+            last4: self.card_last4.clone()?,
+        })
+    }
+}
+
+impl Db {
+    /// Save the card, returns the opaque token for Gateway.Connect
+    pub async fn insert_card_token(&self, card: NewCardToken<'_>) -> sqlx::Result<String> {
+        let token = uuid::Uuid::new_v4().to_string();
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "INSERT INTO card_tokens (token, gateway_card_token, client_id, gateway_reference, source, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(&token)
+            .bind(self.secrets.seal(card.gateway_card_token))
+            .bind(card.client_id)
+            .bind(card.gateway_reference)
+            .bind(card.source)
+            .bind(now())
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(token)
+    }
+
+    /// Card saved for the merchant account that is not revoked and whose first payment was
+    /// approved
+    pub async fn usable_card_token(
+        &self,
+        token: &str,
+        client_id: &str,
+    ) -> sqlx::Result<Option<CardToken>> {
+        let card: Option<CardToken> = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "SELECT c.gateway_card_token, c.gateway_reference, c.source, t.card_fingerprint, t.card_bin, t.card_last4 FROM card_tokens c
+                JOIN transactions t ON t.gateway_reference = c.gateway_reference
+                WHERE c.token = $1 AND c.client_id = $2 AND c.revoked_at IS NULL AND t.status = $3",
+            )
+            .bind(token)
+            .bind(client_id)
+            .bind(connect::Status::Approved)
+            .fetch_optional(pool)
+            .await?
+        });
+        card.map(|mut card| {
+            card.gateway_card_token = self.open(&card.gateway_card_token)?;
+            Ok(card)
+        })
+        .transpose()
+    }
+
+    /// Revoke the card saved for the merchant account. Card kept in the vault is deleted from it,
+    /// a revoked card is never charged again.
+    ///
+    /// Returns `false` if it is not found or is already revoked
+    pub async fn revoke_card_token(&self, token: &str, client_id: &str) -> sqlx::Result<bool> {
+        let revoked: Option<(String, CardSource)> = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "UPDATE card_tokens SET revoked_at = $1 WHERE token = $2 AND client_id = $3 AND revoked_at IS NULL
+                RETURNING gateway_card_token, source",
+            )
+            .bind(now())
+            .bind(token)
+            .bind(client_id)
+            .fetch_optional(pool)
+            .await?
+        });
+        let Some((gateway_card_token, source)) = revoked else {
+            return Ok(false);
+        };
+        if source == CardSource::Vault {
+            self.delete_vault_card(&self.open(&gateway_card_token)?)
+                .await?;
+        }
+        Ok(true)
+    }
+}
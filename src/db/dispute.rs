@@ -0,0 +1,122 @@
+use serde::Serialize;
+
+use crate::{connect::callback::DisputeStatus, money::Money};
+
+use super::{Db, now};
+
+#[derive(Debug)]
+pub struct NewDispute<'a> {
+    /// Dispute id at the gateway
+    pub dispute_reference: &'a str,
+    pub gateway_reference: &'a str,
+    pub status: DisputeStatus,
+    pub reason: &'a str,
+    pub amount: &'a Money,
+}
+
+/// Chargeback or another dispute raised against the transaction
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct DisputeRecord {
+    pub id: i64,
+    pub dispute_reference: String,
+    pub gateway_reference: String,
+    pub status: DisputeStatus,
+    pub reason: String,
+    /// Disputed amount in minor units
+    pub amount: i64,
+    pub currency: String,
+    /// Current status was delivered to Gateway.Connect
+    pub forwarded: bool,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+const DISPUTE_COLUMNS: &str = "id, dispute_reference, gateway_reference, status, reason, amount, currency, forwarded, created_at, updated_at";
+
+impl Db {
+    /// Store the new dispute or the new status of the known one.
+    ///
+    /// Returns `None` when the dispute is known with the same status already
+    pub async fn upsert_dispute(
+        &self,
+        dispute: NewDispute<'_>,
+    ) -> sqlx::Result<Option<DisputeRecord>> {
+        let now = now();
+        let record = with_pool!(self, |pool| {
+            sqlx::query_as(&format!(
+                "INSERT INTO disputes (dispute_reference, gateway_reference, status, reason, amount, currency, forwarded, created_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+                ON CONFLICT (dispute_reference) DO UPDATE SET status = excluded.status, reason = excluded.reason,
+                    amount = excluded.amount, currency = excluded.currency, forwarded = excluded.forwarded, updated_at = excluded.updated_at
+                WHERE disputes.status <> excluded.status
+                RETURNING {DISPUTE_COLUMNS}"
+            ))
+            .bind(dispute.dispute_reference)
+            .bind(dispute.gateway_reference)
+            .bind(dispute.status)
+            .bind(dispute.reason)
+            .bind(dispute.amount.amount)
+            .bind(&dispute.amount.currency)
+            .bind(false)
+            .bind(now)
+            .fetch_optional(pool)
+            .await?
+        });
+        Ok(record)
+    }
+
+    pub async fn dispute(&self, id: i64) -> sqlx::Result<Option<DisputeRecord>> {
+        let record = with_pool!(self, |pool| {
+            sqlx::query_as(&format!(
+                "SELECT {DISPUTE_COLUMNS} FROM disputes WHERE id = $1"
+            ))
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+        });
+        Ok(record)
+    }
+
+    pub async fn dispute_by_reference(
+        &self,
+        dispute_reference: &str,
+    ) -> sqlx::Result<Option<DisputeRecord>> {
+        let record = with_pool!(self, |pool| {
+            sqlx::query_as(&format!(
+                "SELECT {DISPUTE_COLUMNS} FROM disputes WHERE dispute_reference = $1"
+            ))
+            .bind(dispute_reference)
+            .fetch_optional(pool)
+            .await?
+        });
+        Ok(record)
+    }
+
+    /// Mark the dispute forwarded unless its status has changed since
+    pub async fn dispute_forwarded(&self, id: i64, status: DisputeStatus) -> sqlx::Result<bool> {
+        let rows = with_pool!(self, |pool| {
+            sqlx::query("UPDATE disputes SET forwarded = $1 WHERE id = $2 AND status = $3")
+                .bind(true)
+                .bind(id)
+                .bind(status)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(rows > 0)
+    }
+
+    /// Disputes that are not resolved yet, newest first
+    pub async fn open_disputes(&self, limit: i64) -> sqlx::Result<Vec<DisputeRecord>> {
+        let disputes = with_pool!(self, |pool| {
+            sqlx::query_as(&format!(
+                "SELECT {DISPUTE_COLUMNS} FROM disputes WHERE status = $1 ORDER BY created_at DESC, id DESC LIMIT $2"
+            ))
+            .bind(DisputeStatus::Open)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?
+        });
+        Ok(disputes)
+    }
+}
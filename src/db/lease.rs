@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use super::{Db, now};
+
+impl Db {
+    /// Claim the resource for the owner until the lease expires.
+    ///
+    /// Succeeds when the resource is free, its lease has expired (previous owner crashed or hung)
+    /// or it is already held by the same owner, in which case the lease is extended.
+    pub async fn acquire_lease(
+        &self,
+        resource: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> sqlx::Result<bool> {
+        let now = now();
+        let acquired = with_pool!(self, |pool| {
+            sqlx::query(
+                "INSERT INTO job_leases (resource, owner, expires_at) VALUES ($1, $2, $3)
+                ON CONFLICT (resource) DO UPDATE SET owner = excluded.owner, expires_at = excluded.expires_at
+                WHERE job_leases.expires_at <= $4 OR job_leases.owner = excluded.owner",
+            )
+            .bind(resource)
+            .bind(owner)
+            .bind(now + ttl.as_secs() as i64)
+            .bind(now)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(acquired > 0)
+    }
+
+    /// Extend the lease held by the owner. Returns `false` if the lease was taken over
+    pub async fn renew_lease(
+        &self,
+        resource: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> sqlx::Result<bool> {
+        let renewed = with_pool!(self, |pool| {
+            sqlx::query("UPDATE job_leases SET expires_at = $1 WHERE resource = $2 AND owner = $3")
+                .bind(now() + ttl.as_secs() as i64)
+                .bind(resource)
+                .bind(owner)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(renewed > 0)
+    }
+
+    pub async fn release_lease(&self, resource: &str, owner: &str) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query("DELETE FROM job_leases WHERE resource = $1 AND owner = $2")
+                .bind(resource)
+                .bind(owner)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(())
+    }
+}
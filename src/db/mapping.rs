@@ -0,0 +1,45 @@
+use super::{Db, now};
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct MappingValue {
+    pub merchant_private_key: String,
+    pub token: String,
+}
+
+impl Db {
+    pub async fn insert_mapping(
+        &self,
+        merchant_private_key: &str,
+        token: &str,
+        gateway_token: &str,
+    ) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query("INSERT INTO gateway_id_mapping (token, merchant_private_key, gateway_id, created_at) VALUES ($1, $2, $3, $4)")
+                .bind(token)
+                .bind(self.secrets.seal(merchant_private_key))
+                .bind(gateway_token)
+                .bind(now())
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(())
+    }
+
+    pub async fn get_mapping(&self, gateway_token: &str) -> sqlx::Result<Option<MappingValue>> {
+        let mapping: Option<MappingValue> = with_pool!(self, |pool| {
+            sqlx::query_as(
+                "SELECT merchant_private_key, token FROM gateway_id_mapping WHERE gateway_id = $1",
+            )
+            .bind(gateway_token)
+            .fetch_optional(pool)
+            .await?
+        });
+        mapping
+            .map(|mut mapping| {
+                mapping.merchant_private_key = self.open(&mapping.merchant_private_key)?;
+                Ok(mapping)
+            })
+            .transpose()
+    }
+}
@@ -0,0 +1,1002 @@
+//! Storage with SQLite (`sqlite` feature) and PostgreSQL (`postgres` feature) backends.
+//!
+//! Backend is selected by the `DATABASE_URL` scheme. Queries are shared between backends,
+//! so they must stick to the common SQL subset and use `$N` placeholders.
+
+use sqlx::{Database, Decode, Encode, Type, encode::IsNull, error::BoxDynError};
+use time::OffsetDateTime;
+
+use crate::{connect, secret::Secrets};
+
+/// Run the same expression against the pool of the configured backend
+macro_rules! with_pool {
+    ($db:expr, |$pool:ident| $body:expr) => {
+        match &$db.pool {
+            #[cfg(feature = "sqlite")]
+            crate::db::Pool::Sqlite($pool) => $body,
+            #[cfg(feature = "postgres")]
+            crate::db::Pool::Postgres($pool) => $body,
+        }
+    };
+}
+
+pub mod audit;
+mod card_token;
+pub mod dispute;
+mod lease;
+mod mapping;
+mod outbox;
+mod retention;
+mod transaction;
+mod vault;
+
+pub use audit::{NewCallback, NewDiscrepancy, NewReceivedCallback};
+pub use card_token::{CardSource, NewCardToken};
+pub use dispute::NewDispute;
+pub use outbox::NewOutboxCallback;
+pub use transaction::{NewTransaction, Transaction, TransactionFilter, TransactionRecord};
+
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("at least one of `sqlite` or `postgres` features must be enabled");
+
+#[cfg(feature = "sqlite")]
+static SQLITE_MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations/sqlite");
+#[cfg(feature = "postgres")]
+static POSTGRES_MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations/postgres");
+
+#[derive(Debug, Clone)]
+enum Pool {
+    #[cfg(feature = "sqlite")]
+    Sqlite(sqlx::SqlitePool),
+    #[cfg(feature = "postgres")]
+    Postgres(sqlx::PgPool),
+}
+
+#[derive(Debug, Clone)]
+pub struct Db {
+    pool: Pool,
+    secrets: Secrets,
+}
+
+pub fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Columns that hold secrets sealed with [Secrets]: (table, column)
+const SECRET_COLUMNS: [(&str, &str); 3] = [
+    ("gateway_id_mapping", "merchant_private_key"),
+    ("transactions", "client_secret"),
+    ("card_tokens", "gateway_card_token"),
+];
+
+impl Db {
+    pub async fn connect() -> sqlx::Result<Self> {
+        let database_url = std::env::var("DATABASE_URL").expect("database url to be defined");
+        tracing::debug!(%database_url);
+        let secrets = Secrets::from_env().expect("DB_MASTER_KEY is valid");
+        Self::connect_with(&database_url, secrets).await
+    }
+
+    pub async fn connect_with(database_url: &str, secrets: Secrets) -> sqlx::Result<Self> {
+        let scheme = database_url
+            .split_once(':')
+            .map(|(scheme, _)| scheme)
+            .unwrap_or_default();
+        let pool = match scheme {
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Pool::Sqlite(Self::connect_sqlite(database_url).await?),
+            #[cfg(feature = "postgres")]
+            "postgres" | "postgresql" => {
+                let pool = sqlx::PgPool::connect(database_url).await?;
+                POSTGRES_MIGRATOR.run(&pool).await?;
+                Pool::Postgres(pool)
+            }
+            _ => panic!("database url scheme {scheme:?} is not supported by this build"),
+        };
+        Ok(Self { pool, secrets })
+    }
+
+    #[cfg(feature = "sqlite")]
+    async fn connect_sqlite(database_url: &str) -> sqlx::Result<sqlx::SqlitePool> {
+        // In-memory database (`sqlite::memory:`) has no file to create
+        if let Some(path) = database_url.strip_prefix("sqlite://") {
+            let path = std::path::Path::new(path);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .expect("directory is initialized");
+            }
+            tokio::fs::OpenOptions::new()
+                .write(true)
+                .truncate(false)
+                .create(true)
+                .open(path)
+                .await
+                .expect("open database file");
+        }
+        let pool = sqlx::SqlitePool::connect(database_url).await?;
+        SQLITE_MIGRATOR.run(&pool).await?;
+        Ok(pool)
+    }
+
+    fn open(&self, value: &str) -> sqlx::Result<String> {
+        self.secrets
+            .open(value)
+            .map_err(|e| sqlx::Error::Decode(e.into()))
+    }
+
+    /// Encrypt secrets that are stored in plaintext or sealed with an older master key
+    /// with the active master key.
+    ///
+    /// Returns amount of encrypted values
+    pub async fn reencrypt_secrets(&self) -> anyhow::Result<u64> {
+        if !self.secrets.is_enabled() {
+            anyhow::bail!("DB_MASTER_KEY is not defined");
+        }
+        let mut total = 0;
+        for (table, column) in SECRET_COLUMNS {
+            let select = format!("SELECT id, {column} FROM {table}");
+            let rows: Vec<(i64, String)> = with_pool!(self, |pool| {
+                sqlx::query_as(&select).fetch_all(pool).await?
+            });
+            let mut encrypted = 0;
+            for (id, value) in rows {
+                if !self.secrets.needs_reseal(&value) {
+                    continue;
+                }
+                let plaintext = self.secrets.open(&value)?;
+                // Value check guards against rows rewritten concurrently
+                let update =
+                    format!("UPDATE {table} SET {column} = $1 WHERE id = $2 AND {column} = $3");
+                let updated = with_pool!(self, |pool| {
+                    sqlx::query(&update)
+                        .bind(self.secrets.seal(&plaintext))
+                        .bind(id)
+                        .bind(&value)
+                        .execute(pool)
+                        .await?
+                        .rows_affected()
+                });
+                if updated == 1 {
+                    encrypted += 1;
+                } else {
+                    tracing::warn!(
+                        table,
+                        column,
+                        id,
+                        "Row changed during re-encryption, run it again before removing old master keys"
+                    );
+                }
+            }
+            tracing::info!(table, column, encrypted, "Re-encrypted secrets");
+            total += encrypted;
+        }
+        Ok(total)
+    }
+}
+
+/// Store the type as text produced by its `as_str` and read back with `FromStr`
+macro_rules! text_type {
+    ($ty:ty) => {
+        impl<DB: Database> Type<DB> for $ty
+        where
+            str: Type<DB>,
+        {
+            fn type_info() -> DB::TypeInfo {
+                <str as Type<DB>>::type_info()
+            }
+
+            fn compatible(ty: &DB::TypeInfo) -> bool {
+                <str as Type<DB>>::compatible(ty)
+            }
+        }
+
+        impl<'q, DB: Database> Encode<'q, DB> for $ty
+        where
+            String: Encode<'q, DB>,
+        {
+            fn encode_by_ref(
+                &self,
+                buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+            ) -> Result<IsNull, BoxDynError> {
+                self.as_str().to_string().encode_by_ref(buf)
+            }
+        }
+
+        impl<'r, DB: Database> Decode<'r, DB> for $ty
+        where
+            &'r str: Decode<'r, DB>,
+        {
+            fn decode(value: <DB as Database>::ValueRef<'r>) -> Result<Self, BoxDynError> {
+                let value = <&str as Decode<DB>>::decode(value)?;
+                Ok(value.parse()?)
+            }
+        }
+    };
+}
+
+// Statuses are stored as lowercase text in every backend
+text_type!(connect::Status);
+text_type!(connect::callback::DisputeStatus);
+text_type!(card_token::CardSource);
+
+#[cfg(test)]
+pub mod tests {
+    use std::{sync::LazyLock, time::Duration};
+
+    use crate::{
+        card::fingerprint::CardFingerprint,
+        connect::{self, api::payment::Settings, callback::DisputeStatus},
+        db::dispute::DisputeRecord,
+        keyring::{KeyEncoding, KeyRing},
+        money::Money,
+        secret::Secrets,
+    };
+
+    use super::{
+        CardSource, Db, NewCallback, NewCardToken, NewDiscrepancy, NewDispute, NewOutboxCallback,
+        NewTransaction, TransactionFilter, now,
+    };
+
+    fn secrets() -> Secrets {
+        let keys = KeyRing::parse(
+            Some("test:0707070707070707070707070707070707070707070707070707070707070707"),
+            None,
+            None,
+            KeyEncoding::Hex,
+        )
+        .unwrap();
+        Secrets::new(keys.as_ref())
+    }
+
+    /// Fresh in-memory SQLite database
+    #[cfg(feature = "sqlite")]
+    pub async fn sqlite() -> Db {
+        Db::connect_with("sqlite::memory:", secrets())
+            .await
+            .unwrap()
+    }
+
+    /// PostgreSQL database from `TEST_POSTGRES_URL` (e.g. a local container)
+    #[cfg(feature = "postgres")]
+    pub async fn postgres() -> Db {
+        let url = std::env::var("TEST_POSTGRES_URL").expect("TEST_POSTGRES_URL is defined");
+        Db::connect_with(&url, secrets()).await.unwrap()
+    }
+
+    /// Gateway account of test transactions
+    pub static SETTINGS: LazyLock<Settings> = LazyLock::new(|| Settings {
+        client_id: "client".into(),
+        secret: "client secret".into(),
+        sandbox: None,
+        auth_only: None,
+    });
+
+    /// Pending 10.50 USD transaction, `reference` is both the client and the gateway one
+    pub fn new_transaction(reference: &str) -> NewTransaction<'_> {
+        NewTransaction {
+            token: "token",
+            client_reference: reference,
+            gateway_reference: reference,
+            processing_url: "https://example.com/processing",
+            amount: 1050,
+            currency: "USD",
+            settings: &SETTINGS,
+            customer_email: None,
+            customer_ip: None,
+            customer_country: None,
+            auth_only: false,
+            card: None,
+        }
+    }
+
+    /// Run `async fn name(db: Db)` tests against every enabled backend. PostgreSQL ones need
+    /// `TEST_POSTGRES_URL` and run with `--include-ignored`
+    macro_rules! backend_tests {
+        ($($name:ident),* $(,)?) => {
+            #[cfg(feature = "sqlite")]
+            mod sqlite {
+                $(
+                    #[tokio::test]
+                    async fn $name() {
+                        super::$name(super::sqlite().await).await;
+                    }
+                )*
+            }
+
+            #[cfg(feature = "postgres")]
+            mod postgres {
+                $(
+                    #[tokio::test]
+                    #[ignore = "needs TEST_POSTGRES_URL"]
+                    async fn $name() {
+                        super::$name(super::postgres().await).await;
+                    }
+                )*
+            }
+        };
+    }
+
+    backend_tests!(
+        transaction_lifecycle,
+        leases,
+        two_step_payments,
+        card_tokens,
+        outbox,
+        outbox_replacement,
+        disputes,
+        retention,
+        search,
+    );
+
+    async fn transaction_lifecycle(db: Db) {
+        let settings = Settings {
+            client_id: "client".into(),
+            secret: "client secret".into(),
+            sandbox: Some(true),
+            auth_only: None,
+        };
+        let suffix = uuid::Uuid::new_v4();
+        let client_reference = format!("client-{suffix}");
+        let gateway_reference = format!("gateway-{suffix}");
+        db.insert_transaction(NewTransaction {
+            client_reference: &client_reference,
+            customer_email: Some("test@gmail.com"),
+            customer_country: Some("NG"),
+            settings: &settings,
+            ..new_transaction(&gateway_reference)
+        })
+        .await
+        .unwrap();
+        db.insert_mapping("merchant key", "token", &gateway_reference)
+            .await
+            .unwrap();
+
+        let transaction = db
+            .get_transaction(&client_reference)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(transaction.status, connect::Status::Pending);
+        assert_eq!(transaction.amount, 1050);
+        assert_eq!(transaction.settings().secret, "client secret");
+        assert!(transaction.sandbox);
+        let mapping = db.get_mapping(&gateway_reference).await.unwrap().unwrap();
+        assert_eq!(mapping.merchant_private_key, "merchant key");
+
+        // Held transactions are neither polled nor reconciled
+        let created = super::now();
+        let polled = |transactions: Vec<super::Transaction>| {
+            transactions
+                .iter()
+                .any(|t| t.gateway_reference == gateway_reference)
+        };
+        assert!(polled(
+            db.pending_transactions(created - 60, created + 60, 0, 1000)
+                .await
+                .unwrap()
+        ));
+        assert!(db.hold_transaction(&gateway_reference).await.unwrap());
+        assert!(!polled(
+            db.pending_transactions(created - 60, created + 60, 0, 1000)
+                .await
+                .unwrap()
+        ));
+        assert!(!polled(
+            db.transactions_updated_since(0, 0, 1000).await.unwrap()
+        ));
+        let record = db
+            .get_transaction_record(&gateway_reference)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(record.on_hold);
+
+        // Finalizing releases the hold
+
+        assert!(
+            db.finalize_transaction(
+                &gateway_reference,
+                connect::Status::Declined,
+                Some("no funds")
+            )
+            .await
+            .unwrap()
+        );
+        let record = db
+            .get_transaction_record(&gateway_reference)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!record.on_hold);
+
+        // Final status is never overwritten
+        assert!(
+            !db.finalize_transaction(&gateway_reference, connect::Status::Approved, None)
+                .await
+                .unwrap()
+        );
+        let transaction = db
+            .get_transaction(&client_reference)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(transaction.status, connect::Status::Declined);
+        assert!(db.get_transaction("unknown").await.unwrap().is_none());
+    }
+
+    async fn leases(db: Db) {
+        let ttl = Duration::from_secs(60);
+        let resource = format!("transaction:{}", uuid::Uuid::new_v4());
+        assert!(db.acquire_lease(&resource, "a", ttl).await.unwrap());
+        // Held by another instance
+        assert!(!db.acquire_lease(&resource, "b", ttl).await.unwrap());
+        assert!(!db.renew_lease(&resource, "b", ttl).await.unwrap());
+        assert!(db.renew_lease(&resource, "a", ttl).await.unwrap());
+        assert!(db.acquire_lease(&resource, "a", ttl).await.unwrap());
+
+        // Expired lease of the crashed instance is taken over
+        assert!(
+            db.renew_lease(&resource, "a", Duration::ZERO)
+                .await
+                .unwrap()
+        );
+        assert!(db.acquire_lease(&resource, "b", ttl).await.unwrap());
+        assert!(!db.renew_lease(&resource, "a", ttl).await.unwrap());
+        db.release_lease(&resource, "a").await.unwrap();
+        assert!(!db.acquire_lease(&resource, "a", ttl).await.unwrap());
+        db.release_lease(&resource, "b").await.unwrap();
+        assert!(db.acquire_lease(&resource, "a", ttl).await.unwrap());
+    }
+
+    async fn two_step_payments(db: Db) {
+        let suffix = uuid::Uuid::new_v4();
+        let (captured, cancelled) = (format!("captured-{suffix}"), format!("cancelled-{suffix}"));
+        for reference in [&captured, &cancelled] {
+            db.insert_transaction(NewTransaction {
+                auth_only: true,
+                ..new_transaction(reference)
+            })
+            .await
+            .unwrap();
+            let transaction = db
+                .get_transaction_by_gateway_reference(reference)
+                .await
+                .unwrap()
+                .unwrap();
+            // Approval of the auth-only transaction holds the funds
+            let status = transaction.outcome(connect::Status::Approved);
+            assert_eq!(status, connect::Status::Authorized);
+            assert!(
+                db.finalize_transaction(reference, status, None)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        // Partial capture
+        assert!(db.capture_transaction(&captured, 500).await.unwrap());
+        assert!(!db.capture_transaction(&captured, 500).await.unwrap());
+        assert!(!db.cancel_transaction(&captured, "cancelled").await.unwrap());
+        let transaction = db
+            .get_transaction_by_gateway_reference(&captured)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(transaction.status, connect::Status::Approved);
+        assert_eq!(transaction.money(), Money::new(500, "USD"));
+        assert_eq!(
+            transaction.outcome(connect::Status::Approved),
+            connect::Status::Approved
+        );
+
+        assert!(
+            db.cancel_transaction(&cancelled, "cancelled")
+                .await
+                .unwrap()
+        );
+        assert!(!db.capture_transaction(&cancelled, 1050).await.unwrap());
+        let record = db
+            .get_transaction_record(&cancelled)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.status, connect::Status::Declined);
+        assert_eq!(record.captured_amount, None);
+        assert!(record.auth_only);
+    }
+
+    async fn card_tokens(db: Db) {
+        let suffix = uuid::Uuid::new_v4();
+        let (reference, payment_token) = (format!("first-{suffix}"), format!("token-{suffix}"));
+        db.insert_transaction(NewTransaction {
+            token: &payment_token,
+            ..new_transaction(&reference)
+        })
+        .await
+        .unwrap();
+        let token = db
+            .insert_card_token(NewCardToken {
+                client_id: "client",
+                gateway_reference: &reference,
+                gateway_card_token: "gateway card token",
+                source: CardSource::Gateway,
+            })
+            .await
+            .unwrap();
+
+        // Card is usable once the first payment is approved and only by the same merchant
+        assert!(
+            db.usable_card_token(&token, "client")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        db.finalize_transaction(&reference, connect::Status::Approved, None)
+            .await
+            .unwrap();
+        let card = db
+            .usable_card_token(&token, "client")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(card.gateway_card_token, "gateway card token");
+        assert_eq!(card.gateway_reference, reference);
+        assert_eq!(card.source, CardSource::Gateway);
+        assert!(
+            db.usable_card_token(&token, "other")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert!(!db.revoke_card_token(&token, "other").await.unwrap());
+        assert!(db.revoke_card_token(&token, "client").await.unwrap());
+        assert!(!db.revoke_card_token(&token, "client").await.unwrap());
+        assert!(
+            db.usable_card_token(&token, "client")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Erasure removes saved cards and their vault entries
+        let vault_token = format!("vault-{suffix}");
+        db.insert_vault_card(&vault_token, "sealed card", &reference)
+            .await
+            .unwrap();
+        let token = db
+            .insert_card_token(NewCardToken {
+                client_id: "client",
+                gateway_reference: &reference,
+                gateway_card_token: &vault_token,
+                source: CardSource::Vault,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            db.usable_card_token(&token, "client")
+                .await
+                .unwrap()
+                .unwrap()
+                .source,
+            CardSource::Vault
+        );
+        // Revoked card is deleted from the vault
+        let revoked_vault_token = format!("revoked-{suffix}");
+        db.insert_vault_card(&revoked_vault_token, "sealed card", &reference)
+            .await
+            .unwrap();
+        let revoked = db
+            .insert_card_token(NewCardToken {
+                client_id: "client",
+                gateway_reference: &reference,
+                gateway_card_token: &revoked_vault_token,
+                source: CardSource::Vault,
+            })
+            .await
+            .unwrap();
+        assert!(db.revoke_card_token(&revoked, "client").await.unwrap());
+        assert!(db.vault_card(&revoked_vault_token).await.unwrap().is_none());
+        assert!(db.vault_card(&vault_token).await.unwrap().is_some());
+
+        db.erase_customer_data(&payment_token).await.unwrap();
+        assert!(!db.revoke_card_token(&token, "client").await.unwrap());
+        assert!(db.vault_card(&vault_token).await.unwrap().is_none());
+    }
+
+    async fn outbox(db: Db) {
+        let reference = uuid::Uuid::new_v4().to_string();
+        let amount = Money::new(1050, "USD");
+        let callback = || NewOutboxCallback {
+            gateway_reference: &reference,
+            token: "token",
+            status: connect::Status::Declined,
+            reason: Some("no funds"),
+            amount: &amount,
+            dispute_id: None,
+        };
+        let id = db.enqueue_callback(callback()).await.unwrap().unwrap();
+        // Only one callback of the transaction waits at a time
+        assert!(db.enqueue_callback(callback()).await.unwrap().is_none());
+        assert!(db.due_callbacks(i64::MAX).await.unwrap().contains(&id));
+
+        let ttl = Duration::from_secs(60);
+        let claimed = db.claim_callback(id, ttl).await.unwrap().unwrap();
+        assert_eq!(claimed.gateway_reference, reference);
+        assert_eq!(claimed.status, connect::Status::Declined);
+        assert_eq!(claimed.reason.as_deref(), Some("no funds"));
+        assert_eq!((claimed.amount, claimed.attempts), (1050, 1));
+        // Claimed callback is not due for others
+        assert!(db.claim_callback(id, ttl).await.unwrap().is_none());
+        assert!(!db.due_callbacks(i64::MAX).await.unwrap().contains(&id));
+
+        db.callback_failed(id, "unavailable", now()).await.unwrap();
+        let claimed = db.claim_callback(id, ttl).await.unwrap().unwrap();
+        assert_eq!(claimed.attempts, 2);
+        assert!(db.callback_delivered(&claimed).await.unwrap());
+        assert!(
+            db.claim_callback(id, Duration::ZERO)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(db.enqueue_callback(callback()).await.unwrap().is_some());
+    }
+
+    async fn outbox_replacement(db: Db) {
+        let reference = uuid::Uuid::new_v4().to_string();
+        let authorized = Money::new(1050, "USD");
+        let captured = Money::new(500, "USD");
+        let callback = |status, amount| NewOutboxCallback {
+            gateway_reference: &reference,
+            token: "token",
+            status,
+            reason: None,
+            amount,
+            dispute_id: None,
+        };
+        let id = db
+            .enqueue_callback(callback(connect::Status::Authorized, &authorized))
+            .await
+            .unwrap()
+            .unwrap();
+        // Capture before the authorization is delivered takes its place
+        assert!(
+            db.enqueue_callback(callback(connect::Status::Approved, &captured))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let ttl = Duration::from_secs(60);
+        let claimed = db.claim_callback(id, ttl).await.unwrap().unwrap();
+        assert_eq!(
+            (claimed.status, claimed.amount),
+            (connect::Status::Approved, 500)
+        );
+        assert!(db.callback_delivered(&claimed).await.unwrap());
+
+        // Status changed while the previous one was being delivered stays queued
+        let id = db
+            .enqueue_callback(callback(connect::Status::Authorized, &authorized))
+            .await
+            .unwrap()
+            .unwrap();
+        let claimed = db.claim_callback(id, ttl).await.unwrap().unwrap();
+        assert!(
+            db.enqueue_callback(callback(connect::Status::Approved, &captured))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(!db.callback_delivered(&claimed).await.unwrap());
+        db.callback_failed(id, "status changed", now())
+            .await
+            .unwrap();
+        let claimed = db.claim_callback(id, ttl).await.unwrap().unwrap();
+        assert_eq!(
+            (claimed.status, claimed.amount, claimed.attempts),
+            (connect::Status::Approved, 500, 2)
+        );
+    }
+
+    async fn disputes(db: Db) {
+        let reference = uuid::Uuid::new_v4().to_string();
+        let dispute_reference = format!("dispute-{reference}");
+        let amount = Money::new(1050, "USD");
+        let dispute = |status| NewDispute {
+            dispute_reference: &dispute_reference,
+            gateway_reference: &reference,
+            status,
+            reason: "fraud",
+            amount: &amount,
+        };
+        let open = db
+            .upsert_dispute(dispute(DisputeStatus::Open))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((open.amount, open.forwarded), (1050, false));
+        // Same status again is not a change
+        assert!(
+            db.upsert_dispute(dispute(DisputeStatus::Open))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let open_ids = |disputes: Vec<DisputeRecord>| -> Vec<i64> {
+            disputes.into_iter().map(|d| d.id).collect()
+        };
+        assert!(open_ids(db.open_disputes(i64::MAX).await.unwrap()).contains(&open.id));
+
+        // Dispute notification waits next to the transaction callback
+        let callback = |dispute_id| NewOutboxCallback {
+            gateway_reference: &reference,
+            token: "token",
+            status: connect::Status::Approved,
+            reason: None,
+            amount: &amount,
+            dispute_id,
+        };
+        assert!(db.enqueue_callback(callback(None)).await.unwrap().is_some());
+        let id = db
+            .enqueue_callback(callback(Some(open.id)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            db.enqueue_callback(callback(Some(open.id)))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let claimed = db
+            .claim_callback(id, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(claimed.dispute_id, Some(open.id));
+
+        let won = db
+            .upsert_dispute(dispute(DisputeStatus::Won))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((won.id, won.status), (open.id, DisputeStatus::Won));
+        // Open status was delivered after the dispute was won
+        assert!(
+            !db.dispute_forwarded(won.id, DisputeStatus::Open)
+                .await
+                .unwrap()
+        );
+        assert!(
+            db.dispute_forwarded(won.id, DisputeStatus::Won)
+                .await
+                .unwrap()
+        );
+        let stored = db.dispute_by_reference(&dispute_reference).await.unwrap();
+        assert!(stored.unwrap().forwarded);
+        assert!(!open_ids(db.open_disputes(i64::MAX).await.unwrap()).contains(&open.id));
+    }
+
+    async fn retention(db: Db) {
+        let suffix = uuid::Uuid::new_v4();
+        let token = format!("token-{suffix}");
+        let (pending, declined) = (format!("pending-{suffix}"), format!("declined-{suffix}"));
+        let card = CardFingerprint::with_key(Some(&[7; 32]), "4485081333091151");
+        for reference in [&pending, &declined] {
+            db.insert_transaction(NewTransaction {
+                token: &token,
+                amount: 100,
+                currency: "EUR",
+                customer_email: Some("test@gmail.com"),
+                customer_ip: Some("10.0.0.1"),
+                card: Some(&card),
+                ..new_transaction(reference)
+            })
+            .await
+            .unwrap();
+            db.insert_mapping("merchant key", &token, reference)
+                .await
+                .unwrap();
+        }
+        db.finalize_transaction(&declined, connect::Status::Declined, None)
+            .await
+            .unwrap();
+
+        // Database may be shared with other tests, only rows of this test are checked
+        db.purge_mappings(super::now() - 60).await.unwrap();
+        assert!(db.get_mapping(&declined).await.unwrap().is_some());
+        let future = super::now() + 60;
+        assert!(db.purge_mappings(future).await.unwrap() >= 1);
+        // Pending transactions keep their mappings for the callback
+        assert!(db.get_mapping(&pending).await.unwrap().is_some());
+        assert!(db.get_mapping(&declined).await.unwrap().is_none());
+
+        assert!(db.purge_customer_data(future).await.unwrap() >= 2);
+        let record = db.get_transaction_record(&declined).await.unwrap().unwrap();
+        assert_eq!(record.customer_email, None);
+        assert_eq!(
+            (record.card_fingerprint, record.card_bin, record.card_last4),
+            (None, None, None)
+        );
+
+        let vault_token = format!("vault-{suffix}");
+        db.insert_vault_card(&vault_token, "sealed card", &pending)
+            .await
+            .unwrap();
+        let card_token = db
+            .insert_card_token(NewCardToken {
+                client_id: "client",
+                gateway_reference: &pending,
+                gateway_card_token: &vault_token,
+                source: CardSource::Vault,
+            })
+            .await
+            .unwrap();
+        db.finalize_transaction(&pending, connect::Status::Approved, None)
+            .await
+            .unwrap();
+        db.purge_cards(super::now() - 60).await.unwrap();
+        assert!(db.vault_card(&vault_token).await.unwrap().is_some());
+        assert!(db.purge_cards(future).await.unwrap() >= 1);
+        assert!(db.vault_card(&vault_token).await.unwrap().is_none());
+        assert!(
+            db.usable_card_token(&card_token, "client")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(db.erase_customer_data(&token).await.unwrap(), 2);
+        assert_eq!(db.erase_customer_data("unknown").await.unwrap(), 0);
+    }
+
+    async fn search(db: Db) {
+        let token = format!("token-{}", uuid::Uuid::new_v4());
+        let card = CardFingerprint {
+            fingerprint: Some(format!("fingerprint-{token}")),
+            bin: "448508".into(),
+            last4: "1151".into(),
+        };
+        let mut references = Vec::new();
+        for amount in [100, 200, 300] {
+            let reference = uuid::Uuid::new_v4().to_string();
+            db.insert_transaction(NewTransaction {
+                token: &token,
+                amount,
+                currency: "EUR",
+                card: (amount != 200).then_some(&card),
+                ..new_transaction(&reference)
+            })
+            .await
+            .unwrap();
+            references.push(reference);
+        }
+        db.finalize_transaction(&references[2], connect::Status::Approved, None)
+            .await
+            .unwrap();
+
+        let filter = TransactionFilter {
+            token: Some(token.clone()),
+            ..Default::default()
+        };
+        let (page, total) = db.search_transactions(&filter, 2, 0).await.unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(page.len(), 2);
+        let (page, _) = db.search_transactions(&filter, 2, 2).await.unwrap();
+        assert_eq!(page.len(), 1);
+        // Keyset batches used by exports
+        let batch = db.transactions_after(&filter, 0, 2).await.unwrap();
+        assert_eq!(batch[0].gateway_reference, references[0]);
+        let batch = db
+            .transactions_after(&filter, batch[1].id, 2)
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].gateway_reference, references[2]);
+
+        let filter = TransactionFilter {
+            token: Some(token.clone()),
+            status: Some(connect::Status::Pending),
+            amount_min: Some(150),
+            currency: Some("EUR".into()),
+            ..Default::default()
+        };
+        let (page, total) = db.search_transactions(&filter, 10, 0).await.unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(page[0].gateway_reference, references[1]);
+        assert_eq!(page[0].amount, 200);
+        assert!(page[0].card_fingerprint.is_none());
+
+        // Payments made with the same card
+        let filter = TransactionFilter {
+            card_fingerprint: card.fingerprint.clone(),
+            ..Default::default()
+        };
+        let (page, total) = db.search_transactions(&filter, 10, 0).await.unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(page[0].gateway_reference, references[2]);
+        assert_eq!(page[0].card_bin.as_deref(), Some("448508"));
+        assert_eq!(page[0].card_last4.as_deref(), Some("1151"));
+
+        let log =
+            crate::connect::interaction_log::InteractionSpan::enter().interaction_log("status");
+        db.insert_interaction_log(&references[0], &log)
+            .await
+            .unwrap();
+        db.insert_callback(NewCallback {
+            gateway_reference: &references[0],
+            token: &token,
+            status: connect::Status::Declined,
+            reason: Some("no funds"),
+            amount: 100,
+            currency: "EUR",
+            delivered: false,
+            error: Some("max attempts exceeded"),
+        })
+        .await
+        .unwrap();
+        let logs = db.interaction_logs(&references[0]).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].kind, "status");
+        let callbacks = db.callbacks(&references[0]).await.unwrap();
+        assert_eq!(callbacks[0].status, connect::Status::Declined);
+        assert!(!callbacks[0].delivered);
+        assert_eq!(
+            db.last_callback(&references[0]).await.unwrap(),
+            Some((connect::Status::Declined, false))
+        );
+        assert!(db.last_callback(&references[1]).await.unwrap().is_none());
+
+        let updated = db
+            .transactions_updated_since(super::now() - 60, 0, 1000)
+            .await
+            .unwrap();
+        assert!(
+            updated
+                .iter()
+                .any(|t| t.gateway_reference == references[2]
+                    && t.status == connect::Status::Approved)
+        );
+        db.insert_discrepancy(NewDiscrepancy {
+            gateway_reference: &references[1],
+            kind: "status_differs",
+            our_status: Some(connect::Status::Pending),
+            gateway_status: None,
+            details: Some("gateway status is unknown"),
+            fixed: false,
+        })
+        .await
+        .unwrap();
+        let discrepancies = db.discrepancies(super::now() - 60, 1000).await.unwrap();
+        assert!(
+            discrepancies
+                .iter()
+                .any(|d| d.gateway_reference == references[1]
+                    && d.our_status == Some(connect::Status::Pending)
+                    && d.gateway_status.is_none())
+        );
+        let record = db
+            .get_transaction_record(&references[0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.token, token);
+
+        // Erasure removes logs with customer data and the card
+        db.erase_customer_data(&token).await.unwrap();
+        assert!(
+            db.interaction_logs(&references[0])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let record = db
+            .get_transaction_record(&references[0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(record.card_fingerprint.is_none() && record.card_last4.is_none());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{connect, money::Money};
+
+use super::{Db, now};
+
+#[derive(Debug)]
+pub struct NewOutboxCallback<'a> {
+    pub gateway_reference: &'a str,
+    pub token: &'a str,
+    pub status: connect::Status,
+    pub reason: Option<&'a str>,
+    pub amount: &'a Money,
+    /// Queued notification is about the dispute rather than the transaction status
+    pub dispute_id: Option<i64>,
+}
+
+/// Callback waiting to be delivered to Gateway.Connect
+#[derive(Debug, sqlx::FromRow)]
+pub struct OutboxCallback {
+    pub id: i64,
+    pub gateway_reference: String,
+    pub token: String,
+    pub status: connect::Status,
+    pub reason: Option<String>,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: String,
+    /// Delivery attempts including the current one
+    pub attempts: i64,
+    pub dispute_id: Option<i64>,
+}
+
+const OUTBOX_COLUMNS: &str =
+    "id, gateway_reference, token, status, reason, amount, currency, attempts, dispute_id";
+
+impl Db {
+    /// Queue the callback. Transaction status callback that is still waiting takes the new
+    /// status, reason and amount instead, so e.g. capture after authorization is not lost.
+    /// Dispute notification is not queued while another one for the dispute is waiting, it picks
+    /// up the current dispute status on delivery.
+    ///
+    /// Returns id of the queued callback, `None` when the waiting one was updated or kept
+    pub async fn enqueue_callback(
+        &self,
+        callback: NewOutboxCallback<'_>,
+    ) -> sqlx::Result<Option<i64>> {
+        let now = now();
+        if callback.dispute_id.is_none() {
+            let replaced = with_pool!(self, |pool| {
+                sqlx::query(
+                    "UPDATE callback_outbox SET status = $1, reason = $2, amount = $3, currency = $4, updated_at = $5
+                    WHERE gateway_reference = $6 AND dispute_id IS NULL",
+                )
+                .bind(callback.status)
+                .bind(callback.reason)
+                .bind(callback.amount.amount)
+                .bind(&callback.amount.currency)
+                .bind(now)
+                .bind(callback.gateway_reference)
+                .execute(pool)
+                .await?
+                .rows_affected()
+            });
+            if replaced > 0 {
+                return Ok(None);
+            }
+        }
+        let id = with_pool!(self, |pool| {
+            sqlx::query_scalar(
+                "INSERT INTO callback_outbox (gateway_reference, token, status, reason, amount, currency, dispute_id, next_attempt_at, created_at, updated_at)
+                SELECT $1, $2, $3, $4, $5, $6, $7, $8, $8, $8
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM callback_outbox WHERE gateway_reference = $1 AND COALESCE(dispute_id, 0) = COALESCE($7, 0)
+                )
+                RETURNING id",
+            )
+            .bind(callback.gateway_reference)
+            .bind(callback.token)
+            .bind(callback.status)
+            .bind(callback.reason)
+            .bind(callback.amount.amount)
+            .bind(&callback.amount.currency)
+            .bind(callback.dispute_id)
+            .bind(now)
+            .fetch_optional(pool)
+            .await?
+        });
+        Ok(id)
+    }
+
+    /// Ids of callbacks due for delivery, oldest attempts first
+    pub async fn due_callbacks(&self, limit: i64) -> sqlx::Result<Vec<i64>> {
+        let ids = with_pool!(self, |pool| {
+            sqlx::query_scalar(
+                "SELECT id FROM callback_outbox WHERE next_attempt_at <= $1 ORDER BY next_attempt_at LIMIT $2",
+            )
+            .bind(now())
+            .bind(limit)
+            .fetch_all(pool)
+            .await?
+        });
+        Ok(ids)
+    }
+
+    /// Claim the due callback for delivery. It is not due again until `claim_ttl` passes, so it
+    /// is retried if the claimant crashes before recording the result
+    pub async fn claim_callback(
+        &self,
+        id: i64,
+        claim_ttl: std::time::Duration,
+    ) -> sqlx::Result<Option<OutboxCallback>> {
+        let now = now();
+        let callback = with_pool!(self, |pool| {
+            sqlx::query_as(&format!(
+                "UPDATE callback_outbox SET attempts = attempts + 1, next_attempt_at = $1, updated_at = $2
+                WHERE id = $3 AND next_attempt_at <= $2
+                RETURNING {OUTBOX_COLUMNS}"
+            ))
+            .bind(now + claim_ttl.as_secs() as i64)
+            .bind(now)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+        });
+        Ok(callback)
+    }
+
+    /// Remove the delivered callback from the outbox unless it was given a newer status during
+    /// delivery. Returns whether it was removed
+    pub async fn callback_delivered(&self, callback: &OutboxCallback) -> sqlx::Result<bool> {
+        let res = with_pool!(self, |pool| {
+            sqlx::query(
+                "DELETE FROM callback_outbox
+                WHERE id = $1 AND status = $2 AND COALESCE(reason, '') = $3 AND amount = $4 AND currency = $5",
+            )
+            .bind(callback.id)
+            .bind(callback.status)
+            .bind(callback.reason.as_deref().unwrap_or_default())
+            .bind(callback.amount)
+            .bind(&callback.currency)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(res > 0)
+    }
+
+    /// Record failed delivery, callback is due again at `next_attempt_at`
+    pub async fn callback_failed(
+        &self,
+        id: i64,
+        error: &str,
+        next_attempt_at: i64,
+    ) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "UPDATE callback_outbox SET last_error = $1, next_attempt_at = $2, updated_at = $3 WHERE id = $4",
+            )
+            .bind(error)
+            .bind(next_attempt_at)
+            .bind(now())
+            .bind(id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(())
+    }
+
+    /// Callbacks waiting for the transaction, oldest first
+    #[cfg(all(test, feature = "sqlite"))]
+    pub async fn queued_callbacks(
+        &self,
+        gateway_reference: &str,
+    ) -> sqlx::Result<Vec<OutboxCallback>> {
+        let callbacks = with_pool!(self, |pool| {
+            sqlx::query_as(&format!(
+                "SELECT {OUTBOX_COLUMNS} FROM callback_outbox WHERE gateway_reference = $1 ORDER BY id"
+            ))
+            .bind(gateway_reference)
+            .fetch_all(pool)
+            .await?
+        });
+        Ok(callbacks)
+    }
+}
@@ -0,0 +1,131 @@
+use crate::connect;
+
+use super::{Db, now};
+
+impl Db {
+    /// Remove customer and card data of transactions created before the timestamp.
+    ///
+    /// Returns amount of affected transactions
+    pub async fn purge_customer_data(&self, created_before: i64) -> sqlx::Result<u64> {
+        let purged = with_pool!(self, |pool| {
+            sqlx::query(
+                "UPDATE transactions SET customer_email = NULL, customer_ip = NULL, customer_country = NULL,
+                card_fingerprint = NULL, card_bin = NULL, card_last4 = NULL, updated_at = $1
+                WHERE created_at <= $2
+                AND (customer_email IS NOT NULL OR customer_ip IS NOT NULL OR customer_country IS NOT NULL
+                OR card_fingerprint IS NOT NULL OR card_bin IS NOT NULL OR card_last4 IS NOT NULL)",
+            )
+            .bind(now())
+            .bind(created_before)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(purged)
+    }
+
+    /// Delete interaction logs stored before the timestamp, their requests carry customer data.
+    ///
+    /// Returns amount of deleted logs
+    pub async fn purge_interaction_logs(&self, created_before: i64) -> sqlx::Result<u64> {
+        let purged = with_pool!(self, |pool| {
+            sqlx::query("DELETE FROM interaction_logs WHERE created_at <= $1")
+                .bind(created_before)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(purged)
+    }
+
+    /// Delete gateway id mappings of transactions finalized before the timestamp.
+    ///
+    /// Mappings without a transaction (created before transactions were stored) are deleted
+    /// once they are older than the timestamp. Returns amount of deleted mappings
+    pub async fn purge_mappings(&self, finalized_before: i64) -> sqlx::Result<u64> {
+        let purged = with_pool!(self, |pool| {
+            sqlx::query(
+                "DELETE FROM gateway_id_mapping WHERE gateway_id IN (
+                    SELECT gateway_reference FROM transactions WHERE status <> $1 AND updated_at <= $2
+                ) OR (
+                    created_at <= $2
+                    AND NOT EXISTS (SELECT 1 FROM transactions WHERE gateway_reference = gateway_id_mapping.gateway_id)
+                )",
+            )
+            .bind(connect::Status::Pending)
+            .bind(finalized_before)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(purged)
+    }
+
+    /// Delete saved and vaulted cards stored before the timestamp, they can't be charged anymore.
+    ///
+    /// Returns amount of deleted saved cards
+    pub async fn purge_cards(&self, created_before: i64) -> sqlx::Result<u64> {
+        with_pool!(self, |pool| {
+            sqlx::query("DELETE FROM vault_cards WHERE created_at <= $1")
+                .bind(created_before)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        let purged = with_pool!(self, |pool| {
+            sqlx::query("DELETE FROM card_tokens WHERE created_at <= $1")
+                .bind(created_before)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(purged)
+    }
+
+    /// Remove customer data, saved and vaulted cards and interaction logs of every transaction of
+    /// the payment.
+    ///
+    /// Returns amount of transactions found for the token
+    pub async fn erase_customer_data(&self, token: &str) -> sqlx::Result<u64> {
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "DELETE FROM vault_cards WHERE gateway_reference IN (SELECT gateway_reference FROM transactions WHERE token = $1)",
+            )
+            .bind(token)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "DELETE FROM card_tokens WHERE gateway_reference IN (SELECT gateway_reference FROM transactions WHERE token = $1)",
+            )
+            .bind(token)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "DELETE FROM interaction_logs WHERE gateway_reference IN (SELECT gateway_reference FROM transactions WHERE token = $1)",
+            )
+            .bind(token)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        let erased = with_pool!(self, |pool| {
+            sqlx::query(
+                "UPDATE transactions SET customer_email = NULL, customer_ip = NULL, customer_country = NULL,
+                card_fingerprint = NULL, card_bin = NULL, card_last4 = NULL, updated_at = $1
+                WHERE token = $2",
+            )
+            .bind(now())
+            .bind(token)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(erased)
+    }
+}
@@ -0,0 +1,515 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    card::fingerprint::CardFingerprint,
+    connect::{self, api::payment::Settings},
+    money::Money,
+};
+
+use super::{Db, now};
+
+#[derive(Debug)]
+pub struct NewTransaction<'a> {
+    pub token: &'a str,
+    pub client_reference: &'a str,
+    pub gateway_reference: &'a str,
+    pub processing_url: &'a str,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: &'a str,
+    pub settings: &'a Settings,
+    pub customer_email: Option<&'a str>,
+    pub customer_ip: Option<&'a str>,
+    pub customer_country: Option<&'a str>,
+    /// Approval only holds the funds, see [Transaction::outcome]
+    pub auth_only: bool,
+    /// Card the payment is made with, unknown for hosted payments
+    pub card: Option<&'a CardFingerprint>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Transaction {
+    pub id: i64,
+    pub token: String,
+    pub gateway_reference: String,
+    pub processing_url: String,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: String,
+    pub status: connect::Status,
+    pub status_details: Option<String>,
+    pub client_id: String,
+    pub client_secret: String,
+    pub sandbox: bool,
+    /// Waits for manual review, its status is not synchronized with the gateway
+    pub on_hold: bool,
+    pub auth_only: bool,
+    /// Captured amount of the auth-only transaction in minor units
+    pub captured_amount: Option<i64>,
+}
+
+/// Transaction as shown to support staff, without gateway credentials
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct TransactionRecord {
+    pub id: i64,
+    pub token: String,
+    pub client_reference: String,
+    pub gateway_reference: String,
+    /// Amount in minor units
+    pub amount: i64,
+    pub currency: String,
+    pub status: connect::Status,
+    pub status_details: Option<String>,
+    /// Waits for manual review
+    pub on_hold: bool,
+    pub auth_only: bool,
+    /// Captured amount of the auth-only transaction in minor units
+    pub captured_amount: Option<i64>,
+    pub client_id: String,
+    pub sandbox: bool,
+    pub customer_email: Option<String>,
+    pub customer_ip: Option<String>,
+    pub customer_country: Option<String>,
+    /// Keyed fingerprint linking payments made with the same card
+    pub card_fingerprint: Option<String>,
+    pub card_bin: Option<String>,
+    pub card_last4: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+const RECORD_COLUMNS: &str = "id, token, client_reference, gateway_reference, amount, currency, status, status_details, on_hold, auth_only, captured_amount, client_id, sandbox, customer_email, customer_ip, customer_country, card_fingerprint, card_bin, card_last4, created_at, updated_at";
+
+/// Transaction search criteria, every defined field must match
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TransactionFilter {
+    pub token: Option<String>,
+    pub gateway_reference: Option<String>,
+    /// Merchant gateway account
+    pub client_id: Option<String>,
+    pub status: Option<connect::Status>,
+    pub currency: Option<String>,
+    pub card_fingerprint: Option<String>,
+    pub customer_email: Option<String>,
+    pub customer_ip: Option<String>,
+    /// Minimal amount in minor units
+    pub amount_min: Option<i64>,
+    /// Maximal amount in minor units
+    pub amount_max: Option<i64>,
+    /// Unix timestamp, inclusive
+    pub created_from: Option<i64>,
+    /// Unix timestamp, exclusive
+    pub created_to: Option<i64>,
+}
+
+enum Arg<'a> {
+    Text(&'a str),
+    Int(i64),
+}
+
+impl TransactionFilter {
+    /// `WHERE` clause with numbered placeholders and the values to bind
+    fn where_clause(&self) -> (String, Vec<Arg<'_>>) {
+        self.where_clause_after(None)
+    }
+
+    /// Same as [TransactionFilter::where_clause] limited to rows with id greater than `after_id`
+    fn where_clause_after(&self, after_id: Option<i64>) -> (String, Vec<Arg<'_>>) {
+        let mut conditions = Vec::new();
+        let mut args = Vec::new();
+        let mut push = |condition: &str, arg| {
+            args.push(arg);
+            conditions.push(format!("{condition} ${}", args.len()));
+        };
+        if let Some(token) = &self.token {
+            push("token =", Arg::Text(token));
+        }
+        if let Some(reference) = &self.gateway_reference {
+            push("gateway_reference =", Arg::Text(reference));
+        }
+        if let Some(client_id) = &self.client_id {
+            push("client_id =", Arg::Text(client_id));
+        }
+        if let Some(status) = self.status {
+            push("status =", Arg::Text(status.as_str()));
+        }
+        if let Some(currency) = &self.currency {
+            push("currency =", Arg::Text(currency));
+        }
+        if let Some(fingerprint) = &self.card_fingerprint {
+            push("card_fingerprint =", Arg::Text(fingerprint));
+        }
+        if let Some(email) = &self.customer_email {
+            push("customer_email =", Arg::Text(email));
+        }
+        if let Some(ip) = &self.customer_ip {
+            push("customer_ip =", Arg::Text(ip));
+        }
+        if let Some(amount) = self.amount_min {
+            push("amount >=", Arg::Int(amount));
+        }
+        if let Some(amount) = self.amount_max {
+            push("amount <=", Arg::Int(amount));
+        }
+        if let Some(ts) = self.created_from {
+            push("created_at >=", Arg::Int(ts));
+        }
+        if let Some(ts) = self.created_to {
+            push("created_at <", Arg::Int(ts));
+        }
+        if let Some(id) = after_id {
+            push("id >", Arg::Int(id));
+        }
+        match conditions.is_empty() {
+            true => (String::new(), args),
+            false => (format!(" WHERE {}", conditions.join(" AND ")), args),
+        }
+    }
+}
+
+/// Bind [Arg]s in order
+macro_rules! bind_args {
+    ($query:expr, $args:expr) => {{
+        let mut query = $query;
+        for arg in $args {
+            query = match arg {
+                Arg::Text(v) => query.bind(*v),
+                Arg::Int(v) => query.bind(*v),
+            };
+        }
+        query
+    }};
+}
+
+const TRANSACTION_COLUMNS: &str = "id, token, gateway_reference, processing_url, amount, currency, status, status_details, client_id, client_secret, sandbox, on_hold, auth_only, captured_amount";
+
+impl Transaction {
+    /// Captured amount once the auth-only transaction is captured, requested amount otherwise
+    pub fn money(&self) -> Money {
+        Money::new(self.captured_amount.unwrap_or(self.amount), &self.currency)
+    }
+
+    /// Status the gateway outcome means for the transaction: approval of the auth-only one that
+    /// is not captured yet only holds the funds
+    pub fn outcome(&self, status: connect::Status) -> connect::Status {
+        match status {
+            connect::Status::Approved if self.auth_only && !self.status.is_final() => {
+                connect::Status::Authorized
+            }
+            status => status,
+        }
+    }
+
+    /// Gateway settings the transaction was created with
+    pub fn settings(&self) -> Settings {
+        Settings {
+            client_id: self.client_id.clone(),
+            secret: self.client_secret.clone(),
+            sandbox: Some(self.sandbox),
+            auth_only: Some(self.auth_only),
+        }
+    }
+}
+
+impl Db {
+    pub async fn insert_transaction(&self, transaction: NewTransaction<'_>) -> sqlx::Result<()> {
+        let now = now();
+        with_pool!(self, |pool| {
+            sqlx::query(
+            "INSERT INTO transactions (token, client_reference, gateway_reference, processing_url, amount, currency, status, client_id, client_secret, sandbox, customer_email, customer_ip, customer_country, auth_only, card_fingerprint, card_bin, card_last4, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+        )
+        .bind(transaction.token)
+        .bind(transaction.client_reference)
+        .bind(transaction.gateway_reference)
+        .bind(transaction.processing_url)
+        .bind(transaction.amount)
+        .bind(transaction.currency)
+        .bind(connect::Status::Pending)
+        .bind(&transaction.settings.client_id)
+        .bind(self.secrets.seal(&transaction.settings.secret))
+        .bind(transaction.settings.sandbox.unwrap_or(false))
+        .bind(transaction.customer_email)
+        .bind(transaction.customer_ip)
+        .bind(transaction.customer_country)
+        .bind(transaction.auth_only)
+        .bind(transaction.card.and_then(|card| card.fingerprint.as_deref()))
+        .bind(transaction.card.map(|card| card.bin.as_str()))
+        .bind(transaction.card.map(|card| card.last4.as_str()))
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await?
+        .rows_affected()
+        });
+        Ok(())
+    }
+
+    pub async fn get_transaction(
+        &self,
+        client_reference: &str,
+    ) -> sqlx::Result<Option<Transaction>> {
+        self.find_transaction("client_reference", client_reference)
+            .await
+    }
+
+    pub async fn get_transaction_by_gateway_reference(
+        &self,
+        gateway_reference: &str,
+    ) -> sqlx::Result<Option<Transaction>> {
+        self.find_transaction("gateway_reference", gateway_reference)
+            .await
+    }
+
+    /// Transaction by the value of the unique column
+    async fn find_transaction(
+        &self,
+        column: &str,
+        value: &str,
+    ) -> sqlx::Result<Option<Transaction>> {
+        let select = format!("SELECT {TRANSACTION_COLUMNS} FROM transactions WHERE {column} = $1");
+        let transaction: Option<Transaction> = with_pool!(self, |pool| {
+            sqlx::query_as(&select)
+                .bind(value)
+                .fetch_optional(pool)
+                .await?
+        });
+        transaction
+            .map(|mut transaction| {
+                transaction.client_secret = self.open(&transaction.client_secret)?;
+                Ok(transaction)
+            })
+            .transpose()
+    }
+
+    /// Move pending transaction into the final status or into [connect::Status::Authorized].
+    ///
+    /// Returns `false` if transaction is not found or is not pending
+    pub async fn finalize_transaction(
+        &self,
+        gateway_reference: &str,
+        status: connect::Status,
+        details: Option<&str>,
+    ) -> sqlx::Result<bool> {
+        let res = with_pool!(self, |pool| {
+            sqlx::query(
+            "UPDATE transactions SET status = $1, status_details = $2, on_hold = $3, updated_at = $4 WHERE gateway_reference = $5 AND status = $6",
+        )
+        .bind(status)
+        .bind(details)
+        .bind(false)
+        .bind(now())
+        .bind(gateway_reference)
+        .bind(connect::Status::Pending)
+        .execute(pool)
+        .await?
+        .rows_affected()
+        });
+        Ok(res > 0)
+    }
+
+    /// Approve authorized transaction with the captured amount.
+    ///
+    /// Returns `false` if transaction is not found or is not authorized
+    pub async fn capture_transaction(
+        &self,
+        gateway_reference: &str,
+        amount: i64,
+    ) -> sqlx::Result<bool> {
+        let res = with_pool!(self, |pool| {
+            sqlx::query(
+                "UPDATE transactions SET status = $1, captured_amount = $2, updated_at = $3 WHERE gateway_reference = $4 AND status = $5",
+            )
+            .bind(connect::Status::Approved)
+            .bind(amount)
+            .bind(now())
+            .bind(gateway_reference)
+            .bind(connect::Status::Authorized)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(res > 0)
+    }
+
+    /// Decline authorized transaction once its hold is released.
+    ///
+    /// Returns `false` if transaction is not found or is not authorized
+    pub async fn cancel_transaction(
+        &self,
+        gateway_reference: &str,
+        details: &str,
+    ) -> sqlx::Result<bool> {
+        let res = with_pool!(self, |pool| {
+            sqlx::query(
+                "UPDATE transactions SET status = $1, status_details = $2, updated_at = $3 WHERE gateway_reference = $4 AND status = $5",
+            )
+            .bind(connect::Status::Declined)
+            .bind(details)
+            .bind(now())
+            .bind(gateway_reference)
+            .bind(connect::Status::Authorized)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(res > 0)
+    }
+
+    /// Keep pending transaction for manual review, it is not polled until finalized.
+    ///
+    /// Returns `false` if transaction is not found or its status is already final
+    pub async fn hold_transaction(&self, gateway_reference: &str) -> sqlx::Result<bool> {
+        let res = with_pool!(self, |pool| {
+            sqlx::query(
+                "UPDATE transactions SET on_hold = $1, updated_at = $2 WHERE gateway_reference = $3 AND status = $4",
+            )
+            .bind(true)
+            .bind(now())
+            .bind(gateway_reference)
+            .bind(connect::Status::Pending)
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(res > 0)
+    }
+
+    /// Batch of pending transactions created between `created_after` and `created_before` in
+    /// insertion order, starting after `after_id`
+    pub async fn pending_transactions(
+        &self,
+        created_after: i64,
+        created_before: i64,
+        after_id: i64,
+        limit: i64,
+    ) -> sqlx::Result<Vec<Transaction>> {
+        let select = format!(
+            "SELECT {TRANSACTION_COLUMNS} FROM transactions
+            WHERE status = $1 AND on_hold = $2 AND created_at > $3 AND created_at <= $4 AND id > $5 ORDER BY id LIMIT $6"
+        );
+        let transactions: Vec<Transaction> = with_pool!(self, |pool| {
+            sqlx::query_as(&select)
+                .bind(connect::Status::Pending)
+                .bind(false)
+                .bind(created_after)
+                .bind(created_before)
+                .bind(after_id)
+                .bind(limit)
+                .fetch_all(pool)
+                .await?
+        });
+        self.open_transactions(transactions)
+    }
+
+    /// Batch of transactions updated since the timestamp in insertion order, starting after
+    /// `after_id`. Transactions on hold are left for manual review and skipped
+    pub async fn transactions_updated_since(
+        &self,
+        updated_since: i64,
+        after_id: i64,
+        limit: i64,
+    ) -> sqlx::Result<Vec<Transaction>> {
+        let select = format!(
+            "SELECT {TRANSACTION_COLUMNS} FROM transactions WHERE updated_at >= $1 AND on_hold = $2 AND id > $3 ORDER BY id LIMIT $4"
+        );
+        let transactions: Vec<Transaction> = with_pool!(self, |pool| {
+            sqlx::query_as(&select)
+                .bind(updated_since)
+                .bind(false)
+                .bind(after_id)
+                .bind(limit)
+                .fetch_all(pool)
+                .await?
+        });
+        self.open_transactions(transactions)
+    }
+
+    fn open_transactions(&self, transactions: Vec<Transaction>) -> sqlx::Result<Vec<Transaction>> {
+        transactions
+            .into_iter()
+            .map(|mut transaction| {
+                transaction.client_secret = self.open(&transaction.client_secret)?;
+                Ok(transaction)
+            })
+            .collect()
+    }
+
+    /// Transactions matching the filter, newest first, and the total amount of matches
+    pub async fn search_transactions(
+        &self,
+        filter: &TransactionFilter,
+        limit: i64,
+        offset: i64,
+    ) -> sqlx::Result<(Vec<TransactionRecord>, i64)> {
+        let (where_clause, args) = filter.where_clause();
+        let select = format!(
+            "SELECT {RECORD_COLUMNS} FROM transactions{where_clause} ORDER BY created_at DESC, id DESC LIMIT ${} OFFSET ${}",
+            args.len() + 1,
+            args.len() + 2
+        );
+        let count = format!("SELECT COUNT(*) FROM transactions{where_clause}");
+        let (transactions, total) = with_pool!(self, |pool| {
+            let transactions = bind_args!(sqlx::query_as(&select), &args)
+                .bind(limit)
+                .bind(offset)
+                .fetch_all(pool)
+                .await?;
+            let total = bind_args!(sqlx::query_scalar(&count), &args)
+                .fetch_one(pool)
+                .await?;
+            (transactions, total)
+        });
+        Ok((transactions, total))
+    }
+
+    /// Amount of transactions matching the filter
+    pub async fn count_transactions(&self, filter: &TransactionFilter) -> sqlx::Result<i64> {
+        let (where_clause, args) = filter.where_clause();
+        let count = format!("SELECT COUNT(*) FROM transactions{where_clause}");
+        let total = with_pool!(self, |pool| {
+            bind_args!(sqlx::query_scalar(&count), &args)
+                .fetch_one(pool)
+                .await?
+        });
+        Ok(total)
+    }
+
+    pub async fn get_transaction_record(
+        &self,
+        gateway_reference: &str,
+    ) -> sqlx::Result<Option<TransactionRecord>> {
+        let select =
+            format!("SELECT {RECORD_COLUMNS} FROM transactions WHERE gateway_reference = $1");
+        let transaction = with_pool!(self, |pool| {
+            sqlx::query_as(&select)
+                .bind(gateway_reference)
+                .fetch_optional(pool)
+                .await?
+        });
+        Ok(transaction)
+    }
+
+    /// Batch of transactions matching the filter in insertion order, starting after `after_id`.
+    ///
+    /// Used to walk large result sets without holding them in memory
+    pub async fn transactions_after(
+        &self,
+        filter: &TransactionFilter,
+        after_id: i64,
+        limit: i64,
+    ) -> sqlx::Result<Vec<TransactionRecord>> {
+        let (where_clause, args) = filter.where_clause_after(Some(after_id));
+        let select = format!(
+            "SELECT {RECORD_COLUMNS} FROM transactions{where_clause} ORDER BY id LIMIT ${}",
+            args.len() + 1
+        );
+        let transactions = with_pool!(self, |pool| {
+            bind_args!(sqlx::query_as(&select), &args)
+                .bind(limit)
+                .fetch_all(pool)
+                .await?
+        });
+        Ok(transactions)
+    }
+}
@@ -0,0 +1,48 @@
+use super::{Db, now};
+
+impl Db {
+    /// Store the card sealed with the vault key
+    pub async fn insert_vault_card(
+        &self,
+        token: &str,
+        card: &str,
+        gateway_reference: &str,
+    ) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query(
+                "INSERT INTO vault_cards (token, card, gateway_reference, created_at) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(token)
+            .bind(card)
+            .bind(gateway_reference)
+            .bind(now())
+            .execute(pool)
+            .await?
+            .rows_affected()
+        });
+        Ok(())
+    }
+
+    /// Sealed card by its token
+    pub async fn vault_card(&self, token: &str) -> sqlx::Result<Option<String>> {
+        let card = with_pool!(self, |pool| {
+            sqlx::query_scalar("SELECT card FROM vault_cards WHERE token = $1")
+                .bind(token)
+                .fetch_optional(pool)
+                .await?
+        });
+        Ok(card)
+    }
+
+    /// Delete the sealed card, e.g. when it was not saved for the merchant after all
+    pub async fn delete_vault_card(&self, token: &str) -> sqlx::Result<()> {
+        with_pool!(self, |pool| {
+            sqlx::query("DELETE FROM vault_cards WHERE token = $1")
+                .bind(token)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use futures_util::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    connect,
+    db::{Db, TransactionFilter, TransactionRecord},
+    money::Money,
+};
+
+/// Rows fetched from the database at once
+const BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::Ndjson),
+            other => anyhow::bail!("unknown export format {other}, expected csv or ndjson"),
+        }
+    }
+}
+
+/// Column names of [ExportRow], header is written separately so it is present in empty exports
+const CSV_HEADER: [&str; 13] = [
+    "created_at",
+    "updated_at",
+    "token",
+    "client_id",
+    "client_reference",
+    "gateway_reference",
+    "status",
+    "decline_reason",
+    "amount_minor",
+    "amount_major",
+    "currency",
+    "captured_amount_minor",
+    "captured_amount_major",
+];
+
+/// Transaction as exported for finance
+#[derive(Debug, Serialize)]
+struct ExportRow {
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    updated_at: time::OffsetDateTime,
+    token: String,
+    client_id: String,
+    client_reference: String,
+    gateway_reference: String,
+    status: connect::Status,
+    decline_reason: Option<String>,
+    /// Requested or authorized amount
+    amount_minor: i64,
+    amount_major: String,
+    currency: String,
+    /// Set once the authorized transaction is captured, possibly partially
+    captured_amount_minor: Option<i64>,
+    captured_amount_major: Option<String>,
+}
+
+impl TryFrom<TransactionRecord> for ExportRow {
+    type Error = anyhow::Error;
+
+    fn try_from(record: TransactionRecord) -> anyhow::Result<Self> {
+        let timestamp = time::OffsetDateTime::from_unix_timestamp;
+        Ok(Self {
+            created_at: timestamp(record.created_at)?,
+            updated_at: timestamp(record.updated_at)?,
+            token: record.token,
+            client_id: record.client_id,
+            client_reference: record.client_reference,
+            gateway_reference: record.gateway_reference,
+            status: record.status,
+            decline_reason: record
+                .status_details
+                .filter(|_| record.status == connect::Status::Declined),
+            amount_minor: record.amount,
+            amount_major: Money::new(record.amount, &record.currency).major(),
+            captured_amount_minor: record.captured_amount,
+            captured_amount_major: record
+                .captured_amount
+                .map(|amount| Money::new(amount, &record.currency).major()),
+            currency: record.currency,
+        })
+    }
+}
+
+/// Encoded export of the transactions matching the filter, produced in chunks
+/// so large exports are never loaded into memory at once
+pub fn transactions(
+    db: Db,
+    filter: TransactionFilter,
+    format: Format,
+) -> impl Stream<Item = anyhow::Result<Vec<u8>>> + Send + 'static {
+    let header = match format {
+        Format::Csv => Some(Ok(format!("{}\n", CSV_HEADER.join(",")).into_bytes())),
+        Format::Ndjson => None,
+    };
+    // State is the id of the last exported transaction, `None` once everything is exported
+    let batches = stream::try_unfold(Some(0), move |after_id| {
+        let db = db.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(after_id) = after_id else {
+                return Ok(None);
+            };
+            let records = db.transactions_after(&filter, after_id, BATCH_SIZE).await?;
+            if records.is_empty() {
+                return Ok(None);
+            }
+            let next = match records.len() as i64 == BATCH_SIZE {
+                true => records.last().map(|record| record.id),
+                false => None,
+            };
+            let rows = records
+                .into_iter()
+                .map(ExportRow::try_from)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(Some((encode(&rows, format)?, next)))
+        }
+    });
+    stream::iter(header).chain(batches)
+}
+
+fn encode(rows: &[ExportRow], format: Format) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match format {
+        Format::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut buf);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        Format::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut buf, row)?;
+                buf.push(b'\n');
+            }
+        }
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CSV_HEADER, ExportRow, Format, encode};
+    use crate::{connect, db::TransactionRecord};
+
+    fn row() -> ExportRow {
+        ExportRow {
+            created_at: time::OffsetDateTime::from_unix_timestamp(1_764_547_200).unwrap(),
+            updated_at: time::OffsetDateTime::from_unix_timestamp(1_764_547_260).unwrap(),
+            token: "token".into(),
+            client_id: "client".into(),
+            client_reference: "client-ref".into(),
+            gateway_reference: "gateway-ref".into(),
+            status: connect::Status::Declined,
+            decline_reason: Some("Insufficient funds, try again".into()),
+            amount_minor: 1050,
+            amount_major: "10.50".into(),
+            currency: "USD".into(),
+            captured_amount_minor: None,
+            captured_amount_major: None,
+        }
+    }
+
+    #[test]
+    fn amounts() {
+        let record = |amount, currency: &str| TransactionRecord {
+            id: 1,
+            token: "token".into(),
+            client_reference: "client-ref".into(),
+            gateway_reference: "gateway-ref".into(),
+            amount,
+            currency: currency.into(),
+            status: connect::Status::Approved,
+            status_details: None,
+            on_hold: false,
+            auth_only: false,
+            captured_amount: None,
+            client_id: "client".into(),
+            sandbox: false,
+            customer_email: None,
+            customer_ip: None,
+            customer_country: None,
+            card_fingerprint: None,
+            card_bin: None,
+            card_last4: None,
+            created_at: 1_764_547_200,
+            updated_at: 1_764_547_260,
+        };
+        let major = |amount, currency| {
+            ExportRow::try_from(record(amount, currency))
+                .unwrap()
+                .amount_major
+        };
+        assert_eq!(major(1050, "USD"), "10.50");
+        assert_eq!(major(1050, "JPY"), "1050");
+        assert_eq!(major(1050, "KWD"), "1.050");
+
+        let captured = ExportRow::try_from(TransactionRecord {
+            auth_only: true,
+            captured_amount: Some(500),
+            ..record(1050, "USD")
+        })
+        .unwrap();
+        assert_eq!(captured.amount_minor, 1050);
+        assert_eq!(
+            (
+                captured.captured_amount_minor,
+                captured.captured_amount_major.as_deref()
+            ),
+            (Some(500), Some("5.00"))
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let encoded = String::from_utf8(encode(&[row()], Format::Csv).unwrap()).unwrap();
+        assert_eq!(
+            encoded,
+            "2025-12-01T00:00:00Z,2025-12-01T00:01:00Z,token,client,client-ref,gateway-ref,declined,\"Insufficient funds, try again\",1050,10.50,USD,,\n"
+        );
+        let record = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(encoded.as_bytes())
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.len(), CSV_HEADER.len());
+    }
+
+    #[test]
+    fn ndjson() {
+        let encoded = encode(&[row(), row()], Format::Ndjson).unwrap();
+        let lines: Vec<serde_json::Value> = encoded
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["status"], "declined");
+        assert_eq!(lines[0]["amount_major"], "10.50");
+    }
+}
@@ -1,13 +1,39 @@
-use axum::{Json, extract::State, routing::post};
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
 use reqwest::StatusCode;
 use tracing::instrument;
 
 use crate::{
-    connect,
-    gateway::{self, mask},
+    connect::{
+        self,
+        auth::{InboundAuth, authenticate},
+    },
+    db::{Db, NewDiscrepancy, NewDispute, NewReceivedCallback, dispute::DisputeRecord},
+    gateway::{
+        self, StatusEffect,
+        callback::{CallbackPayload, Outcome},
+        dispute::DisputePayload,
+        mask, sync,
+    },
+    jobs::outbox,
+    money::Money,
     state::AppState,
 };
 
+/// Segura callback. It is stored and answered right away, Gateway.Connect is notified in the
+/// background. The answer tells Segura whether to send the callback again:
+///
+/// - 200: callback is processed, including duplicates and ones the transaction state does not
+///   allow, never send it again
+/// - 400: callback can't be read, sending it again does not help
+/// - 404: transaction is unknown (yet)
+/// - 500: callback could not be stored, e.g. database is unavailable, send it again
 #[instrument(skip_all)]
 async fn callback_handler(
     state: State<AppState>,
@@ -17,12 +43,279 @@ async fn callback_handler(
         data = %mask::secure_value(&callback),
         "Received callback from external gateway"
     );
-    let Ok(callback) = serde_json::from_value::<gateway::callback::CallbackPayload>(callback)
-    else {
+    let Ok(callback) = serde_json::from_value::<CallbackPayload>(callback) else {
         tracing::warn!("Failed to deserialize callback body");
-        return StatusCode::INTERNAL_SERVER_ERROR;
+        return StatusCode::BAD_REQUEST;
+    };
+    let paid = match callback.money() {
+        Ok(paid) => paid,
+        Err(e) => {
+            tracing::warn!("Failed to read callback amount: {e}");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    let (outcome, code) = process_callback(&state, &callback, &paid).await;
+    tracing::info!(
+        reference = %callback.order_reference,
+        status = callback.payment_status.as_str(),
+        outcome = outcome.as_str(),
+        "Processed gateway callback"
+    );
+    let received = NewReceivedCallback {
+        gateway_reference: &callback.order_reference,
+        status: callback.payment_status.as_str(),
+        amount: paid.amount,
+        currency: &paid.currency,
+        description: &callback.status_description,
+        outcome: outcome.as_str(),
+    };
+    if let Err(e) = state.db.insert_received_callback(received).await {
+        tracing::error!("Failed to record received callback: {e}");
+    }
+    code
+}
+
+/// Apply the callback following the transaction state machine: pending transaction becomes
+/// final once and only that change is forwarded to Gateway.Connect.
+///
+/// Duplicates and callbacks for final transactions are answered with 200 so the gateway stops
+/// sending them
+async fn process_callback(
+    state: &AppState,
+    callback: &CallbackPayload,
+    paid: &Money,
+) -> (Outcome, StatusCode) {
+    let db = &state.db;
+    let reference = &callback.order_reference;
+    let previous = match db.received_callbacks(reference).await {
+        Ok(previous) => previous,
+        Err(e) => {
+            tracing::error!("Failed to retrieve received callbacks from the database: {e}");
+            return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let received = |failed: bool| {
+        previous.iter().any(|c| {
+            c.status == callback.payment_status.as_str()
+                && (c.outcome == Outcome::Failed.as_str()) == failed
+        })
+    };
+    if received(false) {
+        tracing::info!("Duplicate callback is not forwarded");
+        return (Outcome::Duplicate, StatusCode::OK);
+    }
+    // Same callback was received before but not processed
+    let retry = received(true);
+
+    let (status, details) = match callback.payment_status.effect() {
+        StatusEffect::Intermediate => {
+            tracing::debug!("Transaction stays pending until the final status is received");
+            return (Outcome::Ignored, StatusCode::OK);
+        }
+        StatusEffect::Unknown => {
+            tracing::warn!("Unknown status in callback is not forwarded");
+            return (Outcome::Ignored, StatusCode::OK);
+        }
+        StatusEffect::PostSettlement => return flag_post_settlement(db, callback).await,
+        StatusEffect::Final(connect::Status::Approved) => (connect::Status::Approved, None),
+        StatusEffect::Final(status) => {
+            let details = match callback.status_description.trim() {
+                "" => callback.payment_status.as_str().to_lowercase(),
+                description => description.to_string(),
+            };
+            (status, Some(details))
+        }
+    };
+    let mapping = match db.get_mapping(reference).await {
+        Ok(Some(mapping)) => mapping,
+        Ok(None) => {
+            tracing::warn!("Gateway id mapping is not found in database");
+            return (Outcome::Failed, StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            tracing::error!("Failed to retrieve mapping from the database: {e}");
+            return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let transaction = match db.get_transaction_by_gateway_reference(reference).await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            tracing::error!("Failed to retrieve transaction from the database: {e}");
+            return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let (status, details) = match &transaction {
+        // Status is already stored when forwarding of the same callback failed before
+        Some(transaction) if transaction.status != connect::Status::Pending && retry => {
+            (transaction.status, transaction.status_details.clone())
+        }
+        Some(transaction) if transaction.status.is_final() => {
+            tracing::warn!(
+                status = transaction.status.as_str(),
+                "Transaction is already final, callback is not forwarded"
+            );
+            // E.g. reversal of the approved transaction, someone has to look at it
+            if transaction.status != status {
+                let discrepancy = NewDiscrepancy {
+                    gateway_reference: reference,
+                    kind: "callback_conflicts",
+                    our_status: Some(transaction.status),
+                    gateway_status: Some(status),
+                    details: Some(callback.payment_status.as_str()),
+                    fixed: false,
+                };
+                if let Err(e) = db.insert_discrepancy(discrepancy).await {
+                    tracing::error!("Failed to record discrepancy: {e}");
+                    return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+            return (Outcome::Rejected, StatusCode::OK);
+        }
+        // Authorization failed or expired at the gateway, the funds are not held anymore
+        Some(transaction)
+            if transaction.status == connect::Status::Authorized
+                && status == connect::Status::Declined =>
+        {
+            let reason = details.as_deref().unwrap_or_default();
+            match db.cancel_transaction(reference, reason).await {
+                Ok(true) => {}
+                // Captured or cancelled in the meantime
+                Ok(false) => return (Outcome::Rejected, StatusCode::OK),
+                Err(e) => {
+                    tracing::error!("Failed to update transaction status: {e}");
+                    return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+            (status, details)
+        }
+        Some(transaction) => {
+            let (status, details) = match sync::check_amount(db, transaction, status, details, paid)
+                .await
+            {
+                Ok(sync::Verdict::Apply(status, details)) => (transaction.outcome(status), details),
+                Ok(sync::Verdict::Hold) => {
+                    tracing::warn!(
+                        "Transaction is held for manual review, callback is not forwarded"
+                    );
+                    return (Outcome::Held, StatusCode::OK);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to check callback amount: {e}");
+                    return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            };
+            match db
+                .finalize_transaction(reference, status, details.as_deref())
+                .await
+            {
+                Ok(true) => {}
+                // Status poll or another callback got there first
+                Ok(false) => return (Outcome::Rejected, StatusCode::OK),
+                Err(e) => {
+                    tracing::error!("Failed to update transaction status: {e}");
+                    return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+            (status, details)
+        }
+        // Mappings created before transactions were stored only have received callbacks to go by
+        None => {
+            tracing::warn!("Transaction is not found in database, amount is not checked");
+            if previous
+                .iter()
+                .any(|c| c.outcome == Outcome::Forwarded.as_str())
+            {
+                return (Outcome::Rejected, StatusCode::OK);
+            }
+            (status, details)
+        }
+    };
+    let status = connect::callback::CallbackStatus::new(status, details);
+
+    match outbox::enqueue(db, reference, &mapping.token, &status, paid).await {
+        Ok(queued) => {
+            // Delivery does not hold up the response, failed attempts are retried by the outbox job
+            if let Some(id) = queued.filter(|_| state.deliver_callbacks) {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = outbox::deliver(&db, id).await {
+                        tracing::warn!(id, "Callback delivery failed, it will be retried: {e}");
+                    }
+                });
+            }
+            (Outcome::Forwarded, StatusCode::OK)
+        }
+        Err(e) => {
+            tracing::error!("Failed to queue callback for gateway.connect: {e}");
+            (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Refunds and chargebacks don't change the outcome of the payment, they are recorded as
+/// discrepancies for manual review
+async fn flag_post_settlement(db: &Db, callback: &CallbackPayload) -> (Outcome, StatusCode) {
+    let reference = &callback.order_reference;
+    let transaction = match db.get_transaction_by_gateway_reference(reference).await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            tracing::error!("Failed to retrieve transaction from the database: {e}");
+            return (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let details = format!(
+        "{} {}",
+        callback.payment_status.as_str(),
+        callback.status_description
+    );
+    let discrepancy = NewDiscrepancy {
+        gateway_reference: reference,
+        kind: "post_settlement",
+        our_status: transaction.map(|transaction| transaction.status),
+        gateway_status: None,
+        details: Some(details.trim()),
+        fixed: false,
+    };
+    match db.insert_discrepancy(discrepancy).await {
+        Ok(()) => {
+            tracing::warn!("Post-settlement callback is flagged for manual review");
+            (Outcome::Flagged, StatusCode::OK)
+        }
+        Err(e) => {
+            tracing::error!("Failed to record discrepancy: {e}");
+            (Outcome::Failed, StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Segura dispute notification, e.g. a chargeback and its resolution. It is stored against the
+/// transaction and forwarded to Gateway.Connect as a `chargeback` callback once the sender is
+/// authenticated, otherwise it is recorded for manual review. Answers follow the callback ones:
+/// 200 once stored, including repeated notifications, 400 when it can't be read, 404 for unknown
+/// transactions and 500 when it has to be sent again
+#[instrument(skip_all)]
+async fn dispute_handler(
+    State(state): State<AppState>,
+    axum::Json(notification): Json<serde_json::Value>,
+) -> StatusCode {
+    tracing::trace!(
+        data = %mask::secure_value(&notification),
+        "Received dispute notification from external gateway"
+    );
+    let Ok(notification) = serde_json::from_value::<DisputePayload>(notification) else {
+        tracing::warn!("Failed to deserialize dispute notification body");
+        return StatusCode::BAD_REQUEST;
+    };
+    let (status, amount) = match (notification.dispute_status(), notification.money()) {
+        (Ok(status), Ok(amount)) => (status, amount),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::warn!("Failed to read dispute notification: {e}");
+            return StatusCode::BAD_REQUEST;
+        }
     };
-    let mapping = match state.db.get_mapping(&callback.order_reference).await {
+    let db = &state.db;
+    let reference = &notification.order_reference;
+    let mapping = match db.get_mapping(reference).await {
         Ok(Some(mapping)) => mapping,
         Ok(None) => {
             tracing::warn!("Gateway id mapping is not found in database");
@@ -33,34 +326,344 @@ async fn callback_handler(
             return StatusCode::INTERNAL_SERVER_ERROR;
         }
     };
-    let status = match callback.payment_status {
-        gateway::SeguraStatus::Pending => {
-            tracing::warn!("Unexpected pending status in callback");
-            return StatusCode::OK;
+    let dispute = NewDispute {
+        dispute_reference: &notification.dispute_reference,
+        gateway_reference: reference,
+        status,
+        reason: &notification.reason,
+        amount: &amount,
+    };
+    let dispute = match db.upsert_dispute(dispute).await {
+        Ok(Some(dispute)) if !state.forward_disputes => {
+            return flag_unconfirmed_dispute(db, &dispute).await;
         }
-        gateway::SeguraStatus::Success => connect::callback::CallbackStatus::Approved,
-        gateway::SeguraStatus::Failed => connect::callback::CallbackStatus::Declined {
-            reason: callback.status_description,
+        Ok(Some(dispute)) => {
+            tracing::warn!(
+                dispute = %dispute.dispute_reference,
+                status = status.as_str(),
+                "Dispute is raised against the transaction"
+            );
+            dispute
+        }
+        // Same status again, it is queued once more only if queueing failed before
+        Ok(None) => match db
+            .dispute_by_reference(&notification.dispute_reference)
+            .await
+        {
+            Ok(Some(dispute)) if !dispute.forwarded && state.forward_disputes => dispute,
+            Ok(_) => {
+                tracing::info!("Dispute status is already known, notification is not forwarded");
+                return StatusCode::OK;
+            }
+            Err(e) => {
+                tracing::error!("Failed to retrieve dispute from the database: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
         },
+        Err(e) => {
+            tracing::error!("Failed to store dispute: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
     };
+    match outbox::enqueue_dispute(db, &dispute, &mapping.token).await {
+        Ok(queued) => {
+            if let Some(id) = queued.filter(|_| state.deliver_callbacks) {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = outbox::deliver(&db, id).await {
+                        tracing::warn!(id, "Dispute delivery failed, it will be retried: {e}");
+                    }
+                });
+            }
+            StatusCode::OK
+        }
+        Err(e) => {
+            tracing::error!("Failed to queue dispute for gateway.connect: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    let args = connect::callback::SendArguments {
-        merchant_key: mapping.merchant_private_key,
-        token: mapping.token,
-        currency: callback.currency,
-        status,
-        amount: callback.amount,
+/// Anyone who can reach the gateway listener may send a dispute notification, so without
+/// authentication it is kept for support to confirm with Segura instead of being forwarded
+async fn flag_unconfirmed_dispute(db: &Db, dispute: &DisputeRecord) -> StatusCode {
+    let details = format!(
+        "{} {} is not authenticated, confirm it with Segura",
+        dispute.dispute_reference,
+        dispute.status.as_str()
+    );
+    let discrepancy = NewDiscrepancy {
+        gateway_reference: &dispute.gateway_reference,
+        kind: "unconfirmed_dispute",
+        our_status: None,
+        gateway_status: None,
+        details: Some(&details),
+        fixed: false,
     };
-
-    match connect::callback::send_callback(args).await {
-        Ok(_) => StatusCode::OK,
+    match db.insert_discrepancy(discrepancy).await {
+        Ok(()) => {
+            tracing::warn!(dispute = %dispute.dispute_reference, "Unauthenticated dispute is flagged for manual review");
+            StatusCode::OK
+        }
         Err(e) => {
-            tracing::error!("Failed to send callback to gateway.connect: {e}");
+            tracing::error!("Failed to record discrepancy: {e}");
             StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 }
 
-pub fn router() -> axum::Router<crate::state::AppState> {
-    axum::Router::new().route("/callback", post(callback_handler))
+/// Customer lands here after 3ds or hosted payment page
+#[instrument(skip_all, fields(%reference))]
+async fn return_handler(State(state): State<AppState>, Path(reference): Path<String>) -> Response {
+    let transaction = match state.db.get_transaction(&reference).await {
+        Ok(Some(transaction)) => transaction,
+        Ok(None) => {
+            tracing::warn!("Transaction is not found in database");
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to retrieve transaction from the database: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let redirect = Redirect::to(&transaction.processing_url).into_response();
+    // Held transaction waits for manual review. Customer is not kept waiting for the outcome
+    if transaction.status == connect::Status::Pending && !transaction.on_hold {
+        let db = state.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = gateway::sync::sync_transaction(&db, &transaction).await {
+                tracing::error!("Failed to synchronize transaction status: {e}");
+            }
+        });
+    }
+    redirect
+}
+
+pub fn router(dispute_auth: Arc<InboundAuth>) -> axum::Router<crate::state::AppState> {
+    let disputes = axum::Router::new()
+        .route("/dispute", post(dispute_handler))
+        .route_layer(axum::middleware::from_fn_with_state(
+            dispute_auth,
+            authenticate,
+        ));
+    axum::Router::new()
+        .route("/callback", post(callback_handler))
+        .route("/return/{reference}", get(return_handler))
+        .merge(disputes)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use axum::extract::State;
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::{callback_handler, dispute_handler};
+    use crate::{
+        connect,
+        db::{
+            Db,
+            tests::{new_transaction, sqlite},
+        },
+        state::AppState,
+    };
+
+    /// Queued callbacks stay in the outbox instead of being sent
+    fn state(db: &Db) -> AppState {
+        AppState {
+            deliver_callbacks: false,
+            ..AppState::new(db.clone())
+        }
+    }
+
+    async fn transaction(db: &Db, reference: &str) {
+        db.insert_transaction(new_transaction(reference))
+            .await
+            .unwrap();
+        db.insert_mapping("merchant key", "token", reference)
+            .await
+            .unwrap();
+    }
+
+    async fn receive(db: &Db, reference: &str, status: &str, amount: f64) -> StatusCode {
+        let callback = json!({
+            "currency": "USD",
+            "amount": amount,
+            "orderReference": reference,
+            "paymentStatus": status,
+            "statusDescription": "description",
+        });
+        callback_handler(State(state(db)), axum::Json(callback)).await
+    }
+
+    async fn outcomes(db: &Db, reference: &str) -> Vec<String> {
+        let callbacks = db.received_callbacks(reference).await.unwrap();
+        callbacks.into_iter().map(|c| c.outcome).collect()
+    }
+
+    #[tokio::test]
+    async fn callbacks_are_not_forwarded_twice() {
+        let db = sqlite().await;
+
+        transaction(&db, "approved").await;
+        assert_eq!(
+            receive(&db, "approved", "SUCCESS", 10.5).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            receive(&db, "approved", "SUCCESS", 10.5).await,
+            StatusCode::OK
+        );
+        assert_eq!(outcomes(&db, "approved").await, ["forwarded", "duplicate"]);
+        let queued = db.queued_callbacks("approved").await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(
+            (queued[0].status, queued[0].amount, queued[0].attempts),
+            (connect::Status::Approved, 1050, 0)
+        );
+
+        // Approved amount differs, transaction is held by default
+        transaction(&db, "held").await;
+        assert_eq!(receive(&db, "held", "SUCCESS", 10.0).await, StatusCode::OK);
+        assert_eq!(receive(&db, "held", "SUCCESS", 10.0).await, StatusCode::OK);
+        assert_eq!(receive(&db, "held", "PENDING", 10.5).await, StatusCode::OK);
+        assert_eq!(
+            outcomes(&db, "held").await,
+            ["held", "duplicate", "ignored"]
+        );
+        let record = db.get_transaction_record("held").await.unwrap().unwrap();
+        assert!(record.on_hold);
+        assert_eq!(record.status, connect::Status::Pending);
+        assert!(db.queued_callbacks("held").await.unwrap().is_empty());
+
+        // Failed authorization releases the hold
+        transaction(&db, "authorized").await;
+        db.finalize_transaction("authorized", connect::Status::Authorized, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            receive(&db, "authorized", "EXPIRED", 10.5).await,
+            StatusCode::OK
+        );
+        assert_eq!(outcomes(&db, "authorized").await, ["forwarded"]);
+        let record = db
+            .get_transaction_record("authorized")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.status, connect::Status::Declined);
+        let queued = db.queued_callbacks("authorized").await.unwrap();
+        assert_eq!(queued[0].status, connect::Status::Declined);
+
+        // Failure after success is out of order
+        transaction(&db, "final").await;
+        db.finalize_transaction("final", connect::Status::Approved, None)
+            .await
+            .unwrap();
+        assert_eq!(receive(&db, "final", "FAILED", 10.5).await, StatusCode::OK);
+        assert_eq!(receive(&db, "final", "FAILED", 10.5).await, StatusCode::OK);
+        assert_eq!(outcomes(&db, "final").await, ["rejected", "duplicate"]);
+        assert!(db.callbacks("final").await.unwrap().is_empty());
+
+        // Intermediate, unknown and post-settlement statuses are not forwarded
+        for status in ["PROCESSING", "ON_HOLD", "REFUNDED"] {
+            assert_eq!(receive(&db, "final", status, 10.5).await, StatusCode::OK);
+        }
+        assert_eq!(
+            outcomes(&db, "final").await,
+            ["rejected", "duplicate", "ignored", "ignored", "flagged"]
+        );
+        let kinds: Vec<_> = db
+            .discrepancies(0, 100)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.gateway_reference == "final")
+            .map(|d| d.kind)
+            .collect();
+        assert_eq!(kinds, ["post_settlement", "callback_conflicts"]);
+
+        assert_eq!(
+            receive(&db, "unknown", "SUCCESS", 10.5).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    async fn dispute(state: &AppState, reference: &str, status: &str) -> StatusCode {
+        let notification = json!({
+            "disputeReference": format!("dispute-{reference}"),
+            "orderReference": reference,
+            "currency": "USD",
+            "amount": "10.50",
+            "reason": "fraud",
+            "status": status,
+        });
+        dispute_handler(State(state.clone()), axum::Json(notification)).await
+    }
+
+    #[tokio::test]
+    async fn disputes() {
+        let db = sqlite().await;
+        let authenticated = AppState {
+            forward_disputes: true,
+            ..state(&db)
+        };
+        transaction(&db, "disputed").await;
+        assert_eq!(
+            dispute(&authenticated, "disputed", "OPEN").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            dispute(&authenticated, "disputed", "open").await,
+            StatusCode::OK
+        );
+        let disputes = db.open_disputes(100).await.unwrap();
+        assert_eq!(disputes.len(), 1);
+        assert_eq!(disputes[0].gateway_reference, "disputed");
+        assert_eq!(
+            (disputes[0].amount, disputes[0].reason.as_str()),
+            (1050, "fraud")
+        );
+        let queued = db.queued_callbacks("disputed").await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].dispute_id, Some(disputes[0].id));
+
+        // Waiting notification picks up the new status on delivery
+        assert_eq!(
+            dispute(&authenticated, "disputed", "LOST").await,
+            StatusCode::OK
+        );
+        assert!(db.open_disputes(100).await.unwrap().is_empty());
+        assert_eq!(db.queued_callbacks("disputed").await.unwrap().len(), 1);
+        assert_eq!(
+            dispute(&authenticated, "disputed", "CLOSED").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            dispute(&authenticated, "unknown", "OPEN").await,
+            StatusCode::NOT_FOUND
+        );
+
+        // Unauthenticated dispute is stored for review only
+        transaction(&db, "unconfirmed").await;
+        for _ in 0..2 {
+            assert_eq!(
+                dispute(&state(&db), "unconfirmed", "OPEN").await,
+                StatusCode::OK
+            );
+        }
+        let disputes = db.open_disputes(100).await.unwrap();
+        assert_eq!(disputes.len(), 1);
+        assert!(!disputes[0].forwarded);
+        assert!(db.queued_callbacks("unconfirmed").await.unwrap().is_empty());
+        let kinds: Vec<_> = db
+            .discrepancies(0, 100)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.gateway_reference == "unconfirmed")
+            .map(|d| d.kind)
+            .collect();
+        assert_eq!(kinds, ["unconfirmed_dispute"]);
+    }
 }
@@ -1,10 +1,77 @@
+use crate::money::{MajorAmount, Money};
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CallbackPayload {
     pub currency: String,
-    pub amount: usize,
+    /// Paid amount in major units of the currency
+    pub amount: MajorAmount,
     pub order_reference: String,
     #[serde(default)]
     pub payment_status: super::SeguraStatus,
     pub status_description: String,
 }
+
+impl CallbackPayload {
+    pub fn money(&self) -> anyhow::Result<Money> {
+        self.amount.money(&self.currency)
+    }
+}
+
+/// What was done with a received callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Status was applied and queued for Gateway.Connect
+    Forwarded,
+    /// Same status was received for the transaction before
+    Duplicate,
+    /// Transaction is already final, e.g. `FAILED` after `SUCCESS`
+    Rejected,
+    /// Amount differs, transaction waits for manual review
+    Held,
+    /// Refund or chargeback, recorded for manual review
+    Flagged,
+    /// Status is intermediate or unknown
+    Ignored,
+    /// Not processed, the gateway is expected to send it again
+    Failed,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Forwarded => "forwarded",
+            Self::Duplicate => "duplicate",
+            Self::Rejected => "rejected",
+            Self::Held => "held",
+            Self::Flagged => "flagged",
+            Self::Ignored => "ignored",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallbackPayload;
+    use crate::{gateway::SeguraStatus, money::Money};
+
+    #[test]
+    fn payload() {
+        let callback: CallbackPayload = serde_json::from_str(
+            r#"{
+                "currency": "USD",
+                "amount": 10.5,
+                "orderReference": "gateway reference",
+                "paymentStatus": "SUCCESS",
+                "statusDescription": "Transaction successful"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(callback.payment_status, SeguraStatus::Success);
+        assert_eq!(callback.money().unwrap(), Money::new(1050, "USD"));
+        let callback = r#"{"currency": "JPY", "amount": "1050", "orderReference": "x", "statusDescription": ""}"#;
+        let callback: CallbackPayload = serde_json::from_str(callback).unwrap();
+        assert_eq!(callback.money().unwrap(), Money::new(1050, "JPY"));
+    }
+}
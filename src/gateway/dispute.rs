@@ -0,0 +1,28 @@
+use crate::{
+    connect::callback::DisputeStatus,
+    money::{MajorAmount, Money},
+};
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisputePayload {
+    pub dispute_reference: String,
+    pub order_reference: String,
+    pub currency: String,
+    /// Disputed amount in major units
+    pub amount: MajorAmount,
+    #[serde(default)]
+    pub reason: String,
+    /// `OPEN`, `WON` or `LOST`
+    pub status: String,
+}
+
+impl DisputePayload {
+    pub fn money(&self) -> anyhow::Result<Money> {
+        self.amount.money(&self.currency)
+    }
+
+    pub fn dispute_status(&self) -> anyhow::Result<DisputeStatus> {
+        self.status.trim().to_ascii_lowercase().parse()
+    }
+}
@@ -1,8 +1,11 @@
+use std::collections::BTreeMap;
+
 use crate::{
+    card,
     connect::{
         self,
         api::{
-            RedirectRequest,
+            RedirectRequest, RedirectRequestType,
             payment::{GwConnectH2HPaymentRequest, H2HCardParams},
         },
     },
@@ -12,30 +15,34 @@ use crate::{
 impl<'a> From<&'a connect::api::payment::GwConnectH2HPaymentRequest>
     for super::payin::PaymentInitRequest<'a>
 {
-    fn from(
-        GwConnectH2HPaymentRequest {
+    fn from(pay_request: &'a GwConnectH2HPaymentRequest) -> Self {
+        let GwConnectH2HPaymentRequest {
             payment,
             params,
             processing_url,
             settings,
             ..
-        }: &'a GwConnectH2HPaymentRequest,
-    ) -> Self {
+        } = pay_request;
         let callback_url = std::env::var("CALLBACK_URL")
             .map(|url| format!("{url}/gateway/callback"))
             .ok();
+        // Client reference must be valid uuid
+        let client_reference = uuid::Uuid::new_v4().to_string();
+        // Customer comes back to us first so we can learn the outcome before gateway.connect does
+        let return_url = std::env::var("CALLBACK_URL")
+            .map(|url| format!("{url}/gateway/return/{client_reference}"))
+            .unwrap_or_else(|_| processing_url.clone());
         super::payin::PaymentInitRequest {
-            amount: format!("{:.2}", (payment.gateway_amount as f64 / 100.)),
+            amount: payment.money().major(),
             currency: &payment.gateway_currency,
             email: params.email.as_deref(),
             country: params.country.as_deref(),
             callback_url,
-            return_url: Some(processing_url),
+            return_url: Some(return_url),
             phone_number: params.phone.as_deref(),
             customer_name: params.first_name.as_deref(),
             customer_id: &settings.client_id,
-            // Client reference must be valid uuid
-            client_reference: uuid::Uuid::new_v4().to_string(),
+            client_reference,
             // client_reference: payment.token,
             narration: Some(&payment.product),
             address: params.address.as_deref(),
@@ -44,6 +51,7 @@ impl<'a> From<&'a connect::api::payment::GwConnectH2HPaymentRequest>
             state: params.state.as_deref(),
             zip_code: params.postcode.as_deref(),
             ip_address: payment.ip.as_deref(),
+            capture_mode: pay_request.auth_only().then_some("MANUAL"),
         }
     }
 }
@@ -67,10 +75,7 @@ impl From<(SeguraOkResponse<super::payin::PaymentProcessData>, String)>
                 tracing::trace!("Segura 3ds response");
                 status = three_dspayment_data.status;
                 card_enrolled = true;
-                Some(RedirectRequest {
-                    url: three_dspayment_data.redirect.url,
-                    kind: connect::api::RedirectRequestType::GetWithProcessing,
-                })
+                Some(three_dspayment_data.redirect.into())
             }
         };
         Self {
@@ -78,10 +83,109 @@ impl From<(SeguraOkResponse<super::payin::PaymentProcessData>, String)>
             result: status.into(),
             card_enrolled,
             gateway_token: Some(reference_token),
+            card_token: None,
         }
     }
 }
 
+impl From<super::payin::RedirectData> for RedirectRequest {
+    fn from(
+        super::payin::RedirectData {
+            url,
+            method,
+            target,
+        }: super::payin::RedirectData,
+    ) -> Self {
+        let target = target.trim();
+        match method.to_uppercase().as_str() {
+            "GET" => Self {
+                url,
+                kind: RedirectRequestType::GetWithProcessing,
+                ..Default::default()
+            },
+            "POST" => {
+                let (url, params) = split_query(url);
+                if target.to_lowercase().contains("iframe") {
+                    Self {
+                        url,
+                        kind: RedirectRequestType::PostIframes,
+                        params: Some(params),
+                        html: None,
+                    }
+                } else if target.is_empty() || target.eq_ignore_ascii_case("_self") {
+                    Self {
+                        url,
+                        kind: RedirectRequestType::Post,
+                        params: Some(params),
+                        html: None,
+                    }
+                } else {
+                    // Form has to leave the processing page frame, so we submit it ourselves
+                    let html = render_post_form(&url, &params, target);
+                    Self {
+                        url,
+                        kind: RedirectRequestType::RedirectHtml,
+                        params: None,
+                        html: Some(html),
+                    }
+                }
+            }
+            _ => {
+                tracing::warn!(%method, "Unknown 3ds redirect method, falling back to GET");
+                Self {
+                    url,
+                    kind: RedirectRequestType::GetWithProcessing,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+/// Move query parameters of the url into form fields
+fn split_query(url: String) -> (String, BTreeMap<String, String>) {
+    let Ok(mut parsed) = reqwest::Url::parse(&url) else {
+        return (url, BTreeMap::new());
+    };
+    let params = parsed.query_pairs().into_owned().collect();
+    parsed.set_query(None);
+    (parsed.into(), params)
+}
+
+fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Page with the form that is submitted as soon as it is loaded
+fn render_post_form(url: &str, params: &BTreeMap<String, String>, target: &str) -> String {
+    let inputs: String = params
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                escape_html(name),
+                escape_html(value)
+            )
+        })
+        .collect();
+    format!(
+        r#"<!DOCTYPE html><html><body onload="document.forms[0].submit()"><form method="POST" action="{}" target="{}">{inputs}<noscript><button type="submit">Continue</button></noscript></form></body></html>"#,
+        escape_html(url),
+        escape_html(target),
+    )
+}
+
 impl From<SeguraOkResponse<super::payin::PaymentInitData>>
     for connect::api::GwConnectH2HPaymentResponse
 {
@@ -93,12 +197,14 @@ impl From<SeguraOkResponse<super::payin::PaymentInitData>>
                 .redirect_url
                 .expect("redirect url never empty if hosted_payment"),
             kind: connect::api::RedirectRequestType::GetWithProcessing,
+            ..Default::default()
         };
         Self {
             redirect_request: Some(redirect_request),
             card_enrolled: false,
             result: connect::Status::Pending,
             gateway_token: Some(reference),
+            card_token: None,
         }
     }
 }
@@ -119,9 +225,10 @@ impl<'a> super::payin::ProcessRequest<'a> {
         let expiry_year = year
             .get(2..)
             .expect("reactivepay expires year must be 4 digits");
+        let bin_info = card::bin::lookup(&card_params.pan);
         Self {
             pan: &card_params.pan,
-            cvv: &card_params.cvv,
+            cvv: (!card_params.cvv.is_empty()).then_some(card_params.cvv.as_str()),
             expiry_month,
             expiry_year,
             expiry,
@@ -130,30 +237,175 @@ impl<'a> super::payin::ProcessRequest<'a> {
             cardholder_name: &card_params.holder,
             customer_first_name: params.first_name.as_deref(),
             customer_last_name: params.last_name.as_deref(),
-            card_scheme: payment.card_brand_name.as_ref().map(|v| v.to_uppercase()),
-            // TODO: pass card type???
-            card_type: None,
+            card_scheme: payment
+                .card_brand_name
+                .as_ref()
+                .map(|v| v.to_uppercase())
+                .or_else(|| bin_info.map(|info| info.scheme.clone())),
+            card_type: bin_info
+                .and_then(|info| info.card_type)
+                .map(|card_type| card_type.as_str()),
+            save_card: params.save_card.unwrap_or(false),
+            stored_credential: params.save_card.unwrap_or(false).then_some("INITIAL"),
+            initiator: None,
         }
     }
 }
 
+/// Post-settlement and unknown statuses don't decide the outcome of a pending transaction
 impl From<gateway::SeguraStatus> for connect::Status {
     fn from(value: gateway::SeguraStatus) -> Self {
-        match value {
-            gateway::SeguraStatus::Failed => Self::Declined,
-            gateway::SeguraStatus::Pending => Self::Pending,
-            gateway::SeguraStatus::Success => Self::Approved,
+        match value.effect() {
+            gateway::StatusEffect::Final(status) => status,
+            gateway::StatusEffect::Intermediate
+            | gateway::StatusEffect::PostSettlement
+            | gateway::StatusEffect::Unknown => Self::Pending,
         }
     }
 }
 
-impl From<SeguraStatusResponse> for connect::status::res::Status {
-    fn from(value: SeguraStatusResponse) -> Self {
-        Self {
+impl TryFrom<SeguraStatusResponse> for connect::status::res::Status {
+    type Error = anyhow::Error;
+
+    fn try_from(value: SeguraStatusResponse) -> anyhow::Result<Self> {
+        Ok(Self {
+            amount: value.data.money()?,
             status: value.data.status.into(),
             details: value.message,
-            amount: value.data.amount,
-            currency: value.data.currency,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connect::api::{GwConnectH2HPaymentResponse, RedirectRequest, RedirectRequestType},
+        gateway::{SeguraOkResponse, payin::PaymentProcessData, payin::RedirectData},
+    };
+
+    fn redirect(url: &str, method: &str, target: &str) -> RedirectRequest {
+        RedirectData {
+            url: url.into(),
+            method: method.into(),
+            target: target.into(),
         }
+        .into()
+    }
+
+    #[test]
+    fn get_redirect() {
+        let res = redirect("https://acs.example.com/challenge?id=1", "GET", "_self");
+        assert!(matches!(res.kind, RedirectRequestType::GetWithProcessing));
+        assert_eq!(res.url, "https://acs.example.com/challenge?id=1");
+        assert!(res.params.is_none());
+        assert!(res.html.is_none());
+    }
+
+    #[test]
+    fn post_redirect() {
+        let res = redirect(
+            "https://acs.example.com/challenge?creq=abc%3D&threeDSSessionData=xyz",
+            "post",
+            "_self",
+        );
+        assert!(matches!(res.kind, RedirectRequestType::Post));
+        assert_eq!(res.url, "https://acs.example.com/challenge");
+        let params = res.params.unwrap();
+        assert_eq!(params["creq"], "abc=");
+        assert_eq!(params["threeDSSessionData"], "xyz");
+
+        let res = redirect("https://acs.example.com/challenge", "POST", "");
+        assert!(matches!(res.kind, RedirectRequestType::Post));
+        assert!(res.params.unwrap().is_empty());
+    }
+
+    #[test]
+    fn post_iframe_redirect() {
+        let res = redirect("https://acs.example.com/method?data=1", "POST", "iframe");
+        assert!(matches!(res.kind, RedirectRequestType::PostIframes));
+        assert_eq!(res.url, "https://acs.example.com/method");
+        assert_eq!(res.params.unwrap()["data"], "1");
+    }
+
+    #[test]
+    fn post_top_level_redirect() {
+        let res = redirect(
+            "https://acs.example.com/challenge?creq=\"<a>\"",
+            "POST",
+            "_top",
+        );
+        assert!(matches!(res.kind, RedirectRequestType::RedirectHtml));
+        assert!(res.params.is_none());
+        let html = res.html.unwrap();
+        assert!(html.contains(r#"action="https://acs.example.com/challenge""#));
+        assert!(html.contains(r#"target="_top""#));
+        assert!(html.contains(r#"name="creq" value="&quot;&lt;a&gt;&quot;""#));
+        assert!(html.contains("submit()"));
+    }
+
+    #[test]
+    fn unknown_method_redirect() {
+        let res = redirect("https://acs.example.com/challenge", "PUT", "_self");
+        assert!(matches!(res.kind, RedirectRequestType::GetWithProcessing));
+        assert_eq!(res.url, "https://acs.example.com/challenge");
+    }
+
+    #[test]
+    fn process_response_redirect() {
+        let response: SeguraOkResponse<PaymentProcessData> =
+            serde_json::from_value(serde_json::json!({
+                "requestTime": "2025-11-14 16:32:12",
+                "status": true,
+                "code": 200,
+                "message": "ok",
+                "data": {
+                    "status": "PENDING",
+                    "redirect": {
+                        "url": "https://acs.example.com/challenge?creq=abc",
+                        "method": "POST",
+                        "target": "_self"
+                    }
+                }
+            }))
+            .unwrap();
+        let res = GwConnectH2HPaymentResponse::from((response, "reference".to_string()));
+        assert!(res.card_enrolled);
+        let redirect = res.redirect_request.unwrap();
+        assert!(matches!(redirect.kind, RedirectRequestType::Post));
+        assert_eq!(redirect.params.unwrap()["creq"], "abc");
+    }
+
+    #[test]
+    fn statuses() {
+        use crate::{
+            connect::Status,
+            gateway::{SeguraStatus, StatusEffect},
+        };
+
+        let status = |value: &str| -> SeguraStatus {
+            serde_json::from_value(serde_json::json!(value)).unwrap()
+        };
+        assert_eq!(status("SUCCESS"), SeguraStatus::Success);
+        assert_eq!(status("processing"), SeguraStatus::Processing);
+        assert_eq!(status("CHARGEBACK"), SeguraStatus::Chargeback);
+        assert_eq!(status("ON_HOLD"), SeguraStatus::Unknown("ON_HOLD".into()));
+        assert_eq!(serde_json::json!(status("ON_HOLD")), "ON_HOLD");
+        assert_eq!(serde_json::json!(status("expired")), "EXPIRED");
+
+        assert_eq!(status("PROCESSING").effect(), StatusEffect::Intermediate);
+        assert_eq!(
+            status("ABANDONED").effect(),
+            StatusEffect::Final(Status::Declined)
+        );
+        assert_eq!(
+            status("REVERSED").effect(),
+            StatusEffect::Final(Status::Declined)
+        );
+        assert_eq!(status("REFUNDED").effect(), StatusEffect::PostSettlement);
+        assert_eq!(status("ON_HOLD").effect(), StatusEffect::Unknown);
+        assert_eq!(Status::from(status("SUCCESS")), Status::Approved);
+        assert_eq!(Status::from(status("EXPIRED")), Status::Declined);
+        assert_eq!(Status::from(status("REFUNDED")), Status::Pending);
+        assert_eq!(Status::from(status("ON_HOLD")), Status::Pending);
     }
 }
@@ -36,6 +36,12 @@ fn is_cvv_key(key: &str) -> bool {
     k.contains("cvv") || k.contains("cvc") || k.contains("card_verification") || k.contains("cvn")
 }
 
+/// Return true if a key name likely holds a saved card token.
+fn is_card_token_key(key: &str) -> bool {
+    let k = key.to_lowercase();
+    k.contains("card") && k.contains("token")
+}
+
 pub fn secure_serializable(v: impl Serialize) -> serde_json::Value {
     let value = serde_json::to_value(v).expect("serialization is infallible");
     secure_value(&value)
@@ -49,7 +55,7 @@ pub fn secure_value(v: &serde_json::Value) -> serde_json::Value {
             let mut new = serde_json::Map::with_capacity(map.len());
             for (k, val) in map {
                 let is_pan = is_pan_key(k);
-                let is_cvv = is_cvv_key(k);
+                let is_cvv = is_cvv_key(k) || is_card_token_key(k);
                 let new_val = match val {
                     Value::String(s) if is_pan => Value::String(Masked::mask(s)),
                     Value::String(_) if is_cvv => Value::String("***".to_string()),
@@ -10,20 +10,25 @@ use crate::{
         auth::authenticated_headers,
         error::{ErrorResponse, GatewayError},
     },
+    money::Money,
 };
 
 pub mod api;
 mod auth;
 /// External gateway callback payload
 mod callback;
+/// External gateway dispute notification payload
+mod dispute;
 mod error;
 /// Type conversions between external gateway and gateway.connect
 mod from;
 /// Requisite masking
 pub mod mask;
-mod payin;
+pub mod payin;
 /// External gateway status response
 mod status;
+/// Transaction status synchronization
+pub mod sync;
 
 static BASE_URL: LazyLock<String> =
     LazyLock::new(|| std::env::var("BASE_URL").expect("BASE_URL must be set"));
@@ -50,13 +55,101 @@ pub enum SeguraResponse<T> {
     Err(ErrorResponse),
 }
 
-#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
-#[serde(rename_all = "UPPERCASE")]
+/// Payment status as Segura reports it. Statuses unknown to us are kept as they are, so
+/// responses and callbacks with them can still be read
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum SeguraStatus {
     Failed,
     #[default]
     Pending,
     Success,
+    /// Payment is being processed, e.g. waiting for the bank
+    Processing,
+    /// Authorization was reversed before settlement
+    Reversed,
+    Refunded,
+    Chargeback,
+    /// Customer left the payment page
+    Abandoned,
+    Expired,
+    Unknown(String),
+}
+
+/// What the Segura status means for the transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusEffect {
+    /// Outcome is not known yet
+    Intermediate,
+    /// Outcome of the pending transaction
+    Final(connect::Status),
+    /// Money was returned after the transaction was approved, it needs manual review
+    PostSettlement,
+    Unknown,
+}
+
+impl SeguraStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Failed => "FAILED",
+            Self::Pending => "PENDING",
+            Self::Success => "SUCCESS",
+            Self::Processing => "PROCESSING",
+            Self::Reversed => "REVERSED",
+            Self::Refunded => "REFUNDED",
+            Self::Chargeback => "CHARGEBACK",
+            Self::Abandoned => "ABANDONED",
+            Self::Expired => "EXPIRED",
+            Self::Unknown(status) => status,
+        }
+    }
+
+    pub fn effect(&self) -> StatusEffect {
+        match self {
+            Self::Pending | Self::Processing => StatusEffect::Intermediate,
+            Self::Success => StatusEffect::Final(connect::Status::Approved),
+            Self::Failed | Self::Reversed | Self::Abandoned | Self::Expired => {
+                StatusEffect::Final(connect::Status::Declined)
+            }
+            Self::Refunded | Self::Chargeback => StatusEffect::PostSettlement,
+            Self::Unknown(_) => StatusEffect::Unknown,
+        }
+    }
+}
+
+impl From<&str> for SeguraStatus {
+    fn from(value: &str) -> Self {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "FAILED" => Self::Failed,
+            "PENDING" => Self::Pending,
+            "SUCCESS" => Self::Success,
+            "PROCESSING" => Self::Processing,
+            "REVERSED" => Self::Reversed,
+            "REFUNDED" => Self::Refunded,
+            "CHARGEBACK" => Self::Chargeback,
+            "ABANDONED" => Self::Abandoned,
+            "EXPIRED" => Self::Expired,
+            _ => Self::Unknown(value.to_string()),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SeguraStatus {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let status = String::deserialize(deserializer)?;
+        Ok(Self::from(status.as_str()))
+    }
+}
+
+impl serde::Serialize for SeguraStatus {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
 }
 
 impl<T> SeguraResponse<T> {
@@ -68,6 +161,11 @@ impl<T> SeguraResponse<T> {
     }
 }
 
+/// Customer comes back through `/gateway/return/{reference}` rather than straight to the processing url
+pub fn returns_through_gateway() -> bool {
+    std::env::var("CALLBACK_URL").is_ok()
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum InitRequestUrlSuffix {
     Initialize,
@@ -207,17 +305,74 @@ impl RequestContext {
         Ok(response.into_std_result()?)
     }
 
+    /// Charge the card from the vault on the merchant's initiative, it has no CVV
+    pub async fn process_stored_card(
+        &self,
+        pay_request: &connect::api::payment::GwConnectH2HPaymentRequest,
+        card: &connect::api::payment::H2HCardParams,
+        reference: &str,
+        span: &mut InteractionSpan,
+    ) -> Result<SeguraOkResponse<payin::PaymentProcessData>> {
+        let request = payin::ProcessRequest {
+            save_card: false,
+            stored_credential: Some("SUBSEQUENT"),
+            initiator: Some("MERCHANT"),
+            ..payin::ProcessRequest::from(pay_request, card, reference)
+        };
+        let res: SeguraResponse<_> = self.post("/process", &request, span).await?;
+        Ok(res.into_std_result()?)
+    }
+
+    /// Charge the saved card on the merchant's initiative
+    pub async fn recurring_payment(
+        &self,
+        reference: &str,
+        card_token: &str,
+        span: &mut InteractionSpan,
+    ) -> Result<SeguraOkResponse<payin::PaymentProcessData>> {
+        let request = payin::RecurringRequest {
+            reference,
+            card_token,
+            initiator: "MERCHANT",
+            stored_credential: "SUBSEQUENT",
+        };
+        let res: SeguraResponse<_> = self.post("/recurring", &request, span).await?;
+        Ok(res.into_std_result()?)
+    }
+
+    /// Capture the authorized payment
+    pub async fn capture(
+        &self,
+        reference: &str,
+        amount: &Money,
+        span: &mut InteractionSpan,
+    ) -> Result<SeguraOkResponse<payin::OperationData>> {
+        let request = payin::CaptureRequest {
+            reference,
+            amount: amount.major(),
+        };
+        let res: SeguraResponse<_> = self.post("/capture", &request, span).await?;
+        Ok(res.into_std_result()?)
+    }
+
+    /// Cancel the authorized payment, its funds are released
+    pub async fn void(
+        &self,
+        reference: &str,
+        span: &mut InteractionSpan,
+    ) -> Result<SeguraOkResponse<payin::OperationData>> {
+        let request = payin::VoidRequest { reference };
+        let res: SeguraResponse<_> = self.post("/void", &request, span).await?;
+        Ok(res.into_std_result()?)
+    }
+
     pub async fn hosted_payment(
         &self,
-        pay_request: connect::api::payment::GwConnectH2HPaymentRequest,
+        pay_request: payin::PaymentInitRequest<'_>,
         span: &mut InteractionSpan,
     ) -> Result<SeguraOkResponse<payin::PaymentInitData>> {
         let init_response = self
-            .init(
-                (&pay_request).into(),
-                span,
-                InitRequestUrlSuffix::HostedPayment,
-            )
+            .init(pay_request, span, InitRequestUrlSuffix::HostedPayment)
             .await?;
 
         Ok(init_response)
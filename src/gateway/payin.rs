@@ -1,8 +1,9 @@
-use crate::gateway::SeguraStatus;
+use crate::{gateway::SeguraStatus, money::MajorAmount};
 
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PaymentInitRequest<'a> {
+    /// Amount in major units
     pub amount: String,
     pub currency: &'a str,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -12,7 +13,7 @@ pub struct PaymentInitRequest<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub callback_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub return_url: Option<&'a str>,
+    pub return_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub phone_number: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -32,13 +33,16 @@ pub struct PaymentInitRequest<'a> {
     pub zip_code: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ip_address: Option<&'a str>,
+    /// `MANUAL` to only authorize the payment, it is captured with [CaptureRequest]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_mode: Option<&'static str>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PaymentInitData {
     pub reference: String,
-    pub amount: f64,
+    pub amount: MajorAmount,
     pub currency: String,
     pub redirect_url: Option<String>,
 }
@@ -47,7 +51,9 @@ pub struct PaymentInitData {
 #[serde(rename_all = "camelCase")]
 pub struct ProcessRequest<'a> {
     pub pan: &'a str,
-    pub cvv: &'a str,
+    /// Not known for the card from the vault
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cvv: Option<&'a str>,
     pub expiry: &'a str,
     pub expiry_month: &'a str,
     pub expiry_year: &'a str,
@@ -66,7 +72,29 @@ pub struct ProcessRequest<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub card_scheme: Option<String>, // e.g. "VISA"
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub card_type: Option<&'a str>, // e.g. "DEBIT"
+    pub card_type: Option<&'static str>, // e.g. "DEBIT"
+    /// Ask the gateway for the card token to charge the card later
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub save_card: bool,
+    /// `INITIAL` when the card is saved with this payment, `SUBSEQUENT` when it is charged from
+    /// the vault
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stored_credential: Option<&'static str>,
+    /// `MERCHANT` when the card is charged from the vault
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initiator: Option<&'static str>,
+}
+
+/// Merchant-initiated payment with the saved card, without CVV
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecurringRequest<'a> {
+    pub reference: &'a str,
+    pub card_token: &'a str,
+    /// `MERCHANT`
+    pub initiator: &'static str,
+    /// `SUBSEQUENT`, the card was stored with an earlier payment
+    pub stored_credential: &'static str,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -78,18 +106,34 @@ pub enum PaymentProcessData {
     Standard(StandardPaymentData),
 }
 
+impl PaymentProcessData {
+    pub fn card_token(&self) -> Option<&str> {
+        match self {
+            Self::ThreeDS(data) => data.card_token.as_deref(),
+            Self::Standard(data) => data.card_token.as_deref(),
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StandardPaymentData {
     pub success: bool,
     pub order_reference: String,
     pub status: SeguraStatus,
+    /// Present when the card was saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_token: Option<String>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ThreeDSPaymentData {
     pub status: SeguraStatus,
     pub redirect: RedirectData,
+    /// Present when the card was saved, it can be charged once the payment is approved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_token: Option<String>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -98,3 +142,27 @@ pub struct RedirectData {
     pub method: String,
     pub target: String,
 }
+
+/// Capture of the authorized payment, full or partial
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureRequest<'a> {
+    pub reference: &'a str,
+    /// Amount in major units
+    pub amount: String,
+}
+
+/// Release of the authorized payment funds
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoidRequest<'a> {
+    pub reference: &'a str,
+}
+
+/// Result of capture and void
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationData {
+    pub reference: String,
+    pub status: SeguraStatus,
+}
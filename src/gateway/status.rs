@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     connect::interaction_log::InteractionSpan,
     gateway::{self, RequestContext},
+    money::{MajorAmount, Money},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,13 +17,20 @@ pub struct SeguraStatusResponse {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SeguraStatusData {
     pub currency: String,
-    pub amount: usize,
+    /// Amount in major units of the currency
+    pub amount: MajorAmount,
     #[serde(rename = "paymentReference")]
     pub payment_reference: String,
     #[serde(default)]
     pub status: super::SeguraStatus,
 }
 
+impl SeguraStatusData {
+    pub fn money(&self) -> anyhow::Result<Money> {
+        self.amount.money(&self.currency)
+    }
+}
+
 impl RequestContext {
     pub async fn status(
         &self,
@@ -0,0 +1,300 @@
+use std::sync::LazyLock;
+
+use crate::{
+    connect::{self, interaction_log::InteractionSpan},
+    db::{Db, NewDiscrepancy, Transaction},
+    gateway::RequestContext,
+    jobs::outbox,
+    money::Money,
+};
+
+/// Policy for approvals with amount or currency that differ from the requested ones
+static MISMATCH_POLICY: LazyLock<MismatchPolicy> = LazyLock::new(|| {
+    match std::env::var("AMOUNT_MISMATCH_POLICY")
+        .ok()
+        .filter(|v| !v.is_empty())
+    {
+        Some(v) => v.parse().expect("AMOUNT_MISMATCH_POLICY is valid"),
+        None => MismatchPolicy::Hold,
+    }
+});
+
+/// What to do when the gateway approves a different amount or currency than requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Record the discrepancy and approve
+    Flag,
+    /// Record the discrepancy and keep the transaction pending for manual review
+    Hold,
+    /// Record the discrepancy and decline
+    Decline,
+}
+
+impl std::str::FromStr for MismatchPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(Self::Flag),
+            "hold" => Ok(Self::Hold),
+            "decline" => Ok(Self::Decline),
+            other => {
+                anyhow::bail!("unknown mismatch policy {other}, expected flag, hold or decline")
+            }
+        }
+    }
+}
+
+impl MismatchPolicy {
+    /// Policy configured for this process
+    pub fn global() -> Self {
+        *MISMATCH_POLICY
+    }
+}
+
+/// Status to apply after the amount check
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Apply(connect::Status, Option<String>),
+    Hold,
+}
+
+/// Status to apply for the final status reported by the gateway
+fn verdict(
+    policy: MismatchPolicy,
+    transaction: &Transaction,
+    status: connect::Status,
+    details: Option<String>,
+    paid: &Money,
+) -> Verdict {
+    if status != connect::Status::Approved || paid.matches(&transaction.money()) {
+        return Verdict::Apply(status, details);
+    }
+    match policy {
+        MismatchPolicy::Flag => Verdict::Apply(status, details),
+        MismatchPolicy::Hold => Verdict::Hold,
+        MismatchPolicy::Decline => Verdict::Apply(
+            connect::Status::Declined,
+            Some("Approved amount or currency differs from the requested one".to_string()),
+        ),
+    }
+}
+
+/// Check the final status reported by the gateway against the requested amount and currency.
+///
+/// Mismatches are recorded as discrepancies and handled according to `AMOUNT_MISMATCH_POLICY`,
+/// transaction is put on hold when the policy says so
+pub async fn check_amount(
+    db: &Db,
+    transaction: &Transaction,
+    status: connect::Status,
+    details: Option<String>,
+    paid: &Money,
+) -> sqlx::Result<Verdict> {
+    let policy = MismatchPolicy::global();
+    let requested = transaction.money();
+    let verdict = verdict(policy, transaction, status, details, paid);
+    if status != connect::Status::Approved || paid.matches(&requested) {
+        return Ok(verdict);
+    }
+    let details = format!(
+        "gateway approved {} {}, requested {} {}, policy {policy:?}",
+        paid.major(),
+        paid.currency,
+        requested.major(),
+        requested.currency
+    );
+    tracing::warn!(reference = %transaction.gateway_reference, details, "Approved amount differs from the requested one");
+    db.insert_discrepancy(NewDiscrepancy {
+        gateway_reference: &transaction.gateway_reference,
+        kind: "amount_mismatch",
+        our_status: Some(transaction.status),
+        gateway_status: Some(status),
+        details: Some(&details),
+        fixed: false,
+    })
+    .await?;
+    if verdict == Verdict::Hold {
+        db.hold_transaction(&transaction.gateway_reference).await?;
+    }
+    Ok(verdict)
+}
+
+/// Fetch the transaction status from the gateway and store it.
+///
+/// Gateway.Connect is notified only when this call moves the transaction out of pending, so
+/// concurrent syncs of the same transaction notify it once.
+pub async fn sync_transaction(
+    db: &Db,
+    transaction: &Transaction,
+) -> anyhow::Result<connect::Status> {
+    let ctx = RequestContext::new(&transaction.settings());
+    let mut span = InteractionSpan::enter();
+    let response = ctx.status(&transaction.gateway_reference, &mut span).await;
+    let log = span.interaction_log("status");
+    if let Err(e) = db
+        .insert_interaction_log(&transaction.gateway_reference, &log)
+        .await
+    {
+        tracing::error!("Failed to store interaction log: {e}");
+    }
+    let response = response?;
+    let paid = response.data.money()?;
+    let status: connect::Status = response.data.status.into();
+    if !status.is_final() {
+        return Ok(status);
+    }
+    let details = (status == connect::Status::Declined).then_some(response.message);
+    let verdict = check_amount(db, transaction, status, details, &paid).await?;
+    match verdict {
+        Verdict::Apply(status, details) => {
+            apply_status(db, transaction, status, details).await?;
+            Ok(status)
+        }
+        Verdict::Hold => Ok(connect::Status::Pending),
+    }
+}
+
+/// Move pending transaction into the final status reported by the gateway and notify
+/// Gateway.Connect. Approved auth-only transaction becomes authorized instead.
+///
+/// Returns `false` if the transaction was already final and nothing was sent
+pub async fn apply_status(
+    db: &Db,
+    transaction: &Transaction,
+    status: connect::Status,
+    details: Option<String>,
+) -> anyhow::Result<bool> {
+    let status = transaction.outcome(status);
+    if !db
+        .finalize_transaction(&transaction.gateway_reference, status, details.as_deref())
+        .await?
+    {
+        tracing::debug!(reference = %transaction.gateway_reference, "Transaction is already finalized");
+        return Ok(false);
+    }
+    notify(db, transaction, status, details).await?;
+    tracing::info!(reference = %transaction.gateway_reference, ?status, "Synchronized transaction status");
+    Ok(true)
+}
+
+/// Queue the final transaction status for Gateway.Connect and start the first delivery attempt.
+///
+/// Failed delivery is retried by the outbox job, so only failure to queue is an error. Nothing is
+/// queued while an earlier callback for the transaction is still waiting
+pub async fn notify(
+    db: &Db,
+    transaction: &Transaction,
+    status: connect::Status,
+    details: Option<String>,
+) -> anyhow::Result<()> {
+    let callback_status = connect::callback::CallbackStatus::new(status, details);
+    let queued = outbox::enqueue(
+        db,
+        &transaction.gateway_reference,
+        &transaction.token,
+        &callback_status,
+        &transaction.money(),
+    )
+    .await?;
+    // Delivery does not hold up the caller
+    if let Some(id) = queued {
+        let db = db.clone();
+        let reference = transaction.gateway_reference.clone();
+        tokio::spawn(async move {
+            if let Err(e) = outbox::deliver(&db, id).await {
+                tracing::warn!(%reference, "Callback delivery failed, it will be retried: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MismatchPolicy, Verdict, verdict};
+    use crate::{connect::Status, db::Transaction, money::Money};
+
+    fn transaction() -> Transaction {
+        Transaction {
+            id: 1,
+            token: "token".into(),
+            gateway_reference: "reference".into(),
+            processing_url: "https://example.com".into(),
+            amount: 1050,
+            currency: "USD".into(),
+            status: Status::Pending,
+            status_details: None,
+            client_id: "client".into(),
+            client_secret: "secret".into(),
+            sandbox: true,
+            on_hold: false,
+            auth_only: false,
+            captured_amount: None,
+        }
+    }
+
+    #[test]
+    fn amount_policy() {
+        let transaction = transaction();
+        for policy in [
+            MismatchPolicy::Flag,
+            MismatchPolicy::Hold,
+            MismatchPolicy::Decline,
+        ] {
+            assert_eq!(
+                verdict(
+                    policy,
+                    &transaction,
+                    Status::Approved,
+                    None,
+                    &Money::new(1050, "usd")
+                ),
+                Verdict::Apply(Status::Approved, None)
+            );
+            // Declines are applied whatever the amount is
+            assert_eq!(
+                verdict(
+                    policy,
+                    &transaction,
+                    Status::Declined,
+                    Some("no funds".into()),
+                    &Money::new(1, "EUR")
+                ),
+                Verdict::Apply(Status::Declined, Some("no funds".into()))
+            );
+        }
+        assert_eq!(
+            verdict(
+                MismatchPolicy::Flag,
+                &transaction,
+                Status::Approved,
+                None,
+                &Money::new(1000, "USD")
+            ),
+            Verdict::Apply(Status::Approved, None)
+        );
+        assert_eq!(
+            verdict(
+                MismatchPolicy::Hold,
+                &transaction,
+                Status::Approved,
+                None,
+                &Money::new(1050, "EUR")
+            ),
+            Verdict::Hold
+        );
+        assert!(matches!(
+            verdict(
+                MismatchPolicy::Decline,
+                &transaction,
+                Status::Approved,
+                None,
+                &Money::new(1000, "USD")
+            ),
+            Verdict::Apply(Status::Declined, Some(_))
+        ));
+        assert!("hold".parse::<MismatchPolicy>().is_ok());
+        assert!("approve".parse::<MismatchPolicy>().is_err());
+    }
+}
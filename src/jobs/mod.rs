@@ -0,0 +1,95 @@
+//! Periodic background jobs.
+//!
+//! Every replica runs the same jobs, work items are claimed with expiring leases stored in the
+//! database so each of them is processed by exactly one instance. Leases are renewed while the
+//! item is processed and are taken over by another instance once the owner crashes.
+
+use std::{
+    future::Future,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use crate::db::Db;
+
+pub mod outbox;
+pub mod reconcile;
+pub mod retention;
+pub mod status_poll;
+
+/// Lease owner id of this process
+static INSTANCE_ID: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().to_string());
+
+/// Default lifetime of a lease, renewed every third of it while work is in progress
+const LEASE_TTL: Duration = Duration::from_secs(60);
+
+/// Start all background jobs
+pub fn spawn(db: Db) -> anyhow::Result<()> {
+    let status_poll = status_poll::Config::from_env()?;
+    let retention = retention::Config::from_env()?;
+    let reconcile = reconcile::Config::from_env()?;
+    let outbox = outbox::Config::from_env()?;
+    tracing::info!(instance = %*INSTANCE_ID, "Starting background jobs");
+    tokio::spawn(status_poll::run(db.clone(), status_poll));
+    tokio::spawn(retention::run(db.clone(), retention));
+    tokio::spawn(reconcile::run(db.clone(), reconcile));
+    tokio::spawn(outbox::run(db, outbox));
+    Ok(())
+}
+
+/// Run the future while holding the lease on the resource.
+///
+/// Returns `None` without running it when the resource is claimed by another instance.
+/// If the lease is lost before the work is done (e.g. database was unavailable for longer than
+/// the lease ttl) the work is cancelled, since another instance may have already taken it over.
+pub async fn with_lease<F: Future>(
+    db: &Db,
+    resource: &str,
+    work: F,
+) -> sqlx::Result<Option<F::Output>> {
+    let owner = INSTANCE_ID.as_str();
+    if !db.acquire_lease(resource, owner, LEASE_TTL).await? {
+        return Ok(None);
+    }
+    let keep_alive = async {
+        let mut interval = tokio::time::interval(LEASE_TTL / 3);
+        interval.tick().await;
+        let mut renewed = Instant::now();
+        loop {
+            interval.tick().await;
+            match db.renew_lease(resource, owner, LEASE_TTL).await {
+                Ok(true) => renewed = Instant::now(),
+                Ok(false) => break,
+                Err(e) => {
+                    tracing::warn!(resource, "Failed to renew lease: {e}");
+                    // Lease has expired without a successful renewal
+                    if renewed.elapsed() >= LEASE_TTL {
+                        break;
+                    }
+                }
+            }
+        }
+    };
+    let output = tokio::select! {
+        output = work => Some(output),
+        _ = keep_alive => {
+            tracing::warn!(resource, "Lease is lost, work is cancelled");
+            None
+        }
+    };
+    if output.is_some() {
+        db.release_lease(resource, owner).await?;
+    }
+    Ok(output)
+}
+
+/// Read duration in seconds from env variable
+fn env_secs(name: &str, default: u64) -> anyhow::Result<Duration> {
+    let secs = match std::env::var(name).ok().filter(|v| !v.is_empty()) {
+        Some(v) => v
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{name} must be amount of seconds: {e}"))?,
+        None => default,
+    };
+    Ok(Duration::from_secs(secs))
+}
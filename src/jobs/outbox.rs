@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use crate::{
+    connect::callback::{CallbackStatus, SendArguments, send_callback, send_dispute},
+    db::{self, Db, NewOutboxCallback, dispute::DisputeRecord},
+    money::Money,
+};
+
+/// Callbacks delivered per run
+const BATCH_SIZE: i64 = 100;
+/// Time a claimed callback is not retried by others while it is being delivered
+const CLAIM_TTL: Duration = Duration::from_secs(5 * 60);
+/// Delay after the first failed attempt, doubled after each next one
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Delivery of queued callbacks to Gateway.Connect
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Time between runs, disabled when zero
+    pub interval: Duration,
+}
+
+impl Config {
+    /// Read `OUTBOX_INTERVAL` (seconds)
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            interval: super::env_secs("OUTBOX_INTERVAL", 10)?,
+        })
+    }
+}
+
+pub async fn run(db: Db, config: Config) {
+    if config.interval.is_zero() {
+        tracing::info!("Callback outbox delivery is disabled");
+        return;
+    }
+    let mut interval = tokio::time::interval(config.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let ids = match db.due_callbacks(BATCH_SIZE).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                tracing::error!("Failed to retrieve queued callbacks: {e}");
+                continue;
+            }
+        };
+        for id in ids {
+            if let Err(e) = deliver(&db, id).await {
+                tracing::warn!(id, "Failed to deliver queued callback: {e}");
+            }
+        }
+    }
+}
+
+/// Queue the callback for delivery. Returns its id, `None` when the callback waiting for the
+/// transaction took the new status instead
+pub async fn enqueue(
+    db: &Db,
+    gateway_reference: &str,
+    token: &str,
+    status: &CallbackStatus,
+    amount: &Money,
+) -> sqlx::Result<Option<i64>> {
+    let reason = match status {
+        CallbackStatus::Declined { reason } | CallbackStatus::Chargeback { reason, .. } => {
+            Some(reason.as_str())
+        }
+        CallbackStatus::Approved | CallbackStatus::Authorized => None,
+    };
+    db.enqueue_callback(NewOutboxCallback {
+        gateway_reference,
+        token,
+        status: status.into(),
+        reason,
+        amount,
+        dispute_id: None,
+    })
+    .await
+}
+
+/// Queue notification about the current dispute status. Returns `None` when one for the dispute
+/// is already waiting, it picks up the current status on delivery
+pub async fn enqueue_dispute(
+    db: &Db,
+    dispute: &DisputeRecord,
+    token: &str,
+) -> sqlx::Result<Option<i64>> {
+    db.enqueue_callback(NewOutboxCallback {
+        gateway_reference: &dispute.gateway_reference,
+        token,
+        status: crate::connect::Status::Approved,
+        reason: Some(&dispute.reason),
+        amount: &Money::new(dispute.amount, &dispute.currency),
+        dispute_id: Some(dispute.id),
+    })
+    .await
+}
+
+/// Make a delivery attempt of the queued callback unless it was delivered, is claimed by someone
+/// else or is not due yet. Failed attempts are retried with growing delays
+pub async fn deliver(db: &Db, id: i64) -> anyhow::Result<()> {
+    let Some(callback) = db.claim_callback(id, CLAIM_TTL).await? else {
+        return Ok(());
+    };
+    let result = async {
+        let Some(mapping) = db.get_mapping(&callback.gateway_reference).await? else {
+            anyhow::bail!("gateway id mapping is not found in database");
+        };
+        if let Some(dispute_id) = callback.dispute_id {
+            let Some(dispute) = db.dispute(dispute_id).await? else {
+                anyhow::bail!("dispute is not found in database");
+            };
+            let args = SendArguments {
+                merchant_key: mapping.merchant_private_key,
+                token: callback.token.clone(),
+                status: CallbackStatus::Chargeback {
+                    reason: dispute.reason,
+                    dispute_reference: dispute.dispute_reference,
+                    dispute_status: dispute.status,
+                },
+                amount: Money::new(dispute.amount, &dispute.currency),
+            };
+            send_dispute(args).await?;
+            // Status changed during delivery, the new one is sent on the next attempt
+            if !db.dispute_forwarded(dispute.id, dispute.status).await? {
+                anyhow::bail!("dispute status has changed during delivery");
+            }
+            return Ok(());
+        }
+        let args = SendArguments {
+            merchant_key: mapping.merchant_private_key,
+            token: callback.token.clone(),
+            status: CallbackStatus::new(callback.status, callback.reason.clone()),
+            amount: Money::new(callback.amount, &callback.currency),
+        };
+        send_callback(db, &callback.gateway_reference, args).await
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            if !db.callback_delivered(&callback).await? {
+                // Newer status is sent right on the next run
+                db.callback_failed(callback.id, "status changed during delivery", db::now())
+                    .await?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            let next_attempt_at = db::now() + retry_delay(callback.attempts).as_secs() as i64;
+            db.callback_failed(callback.id, &e.to_string(), next_attempt_at)
+                .await?;
+            Err(e)
+        }
+    }
+}
+
+/// Delay before the next attempt after `attempts` failed ones
+fn retry_delay(attempts: i64) -> Duration {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    (RETRY_DELAY * 2u32.pow(exponent)).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::retry_delay;
+
+    #[test]
+    fn retry_delays() {
+        assert_eq!(retry_delay(1), Duration::from_secs(30));
+        assert_eq!(retry_delay(2), Duration::from_secs(60));
+        assert_eq!(retry_delay(5), Duration::from_secs(480));
+        assert_eq!(retry_delay(10), Duration::from_secs(60 * 60));
+        assert_eq!(retry_delay(1000), Duration::from_secs(60 * 60));
+    }
+}
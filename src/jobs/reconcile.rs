@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    connect::{self, interaction_log::InteractionSpan},
+    db::{self, Db, NewDiscrepancy, Transaction},
+    gateway::{
+        RequestContext, StatusEffect,
+        sync::{Verdict, apply_status, check_amount, notify},
+    },
+};
+
+/// Transactions fetched from the database at once
+const BATCH_SIZE: i64 = 100;
+const LEASE: &str = "job:reconcile";
+
+/// Reconciliation of recently updated transactions against the gateway status API
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Time between runs, disabled when zero
+    pub interval: Duration,
+    /// Transactions updated within this time before the run are checked
+    pub window: Duration,
+    /// Maximal status requests per second
+    pub rate: u32,
+}
+
+/// Results of a reconciliation run
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub checked: u64,
+    pub discrepancies: u64,
+    pub fixed: u64,
+    /// Transactions that could not be checked
+    pub failed: u64,
+}
+
+impl Config {
+    /// Read `RECONCILE_INTERVAL`, `RECONCILE_WINDOW` (seconds) and `RECONCILE_RATE`
+    /// (requests per second)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let rate = match std::env::var("RECONCILE_RATE")
+            .ok()
+            .filter(|v| !v.is_empty())
+        {
+            Some(v) => v
+                .parse()
+                .map_err(|e| anyhow::anyhow!("RECONCILE_RATE must be a positive number: {e}"))?,
+            None => 5,
+        };
+        if rate == 0 {
+            anyhow::bail!("RECONCILE_RATE must be a positive number");
+        }
+        Ok(Self {
+            interval: super::env_secs("RECONCILE_INTERVAL", 24 * 60 * 60)?,
+            window: super::env_secs("RECONCILE_WINDOW", 24 * 60 * 60)?,
+            rate,
+        })
+    }
+}
+
+pub async fn run(db: Db, config: Config) {
+    if config.interval.is_zero() {
+        tracing::info!("Status reconciliation is disabled");
+        return;
+    }
+    let mut interval = tokio::time::interval(config.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        // Lease is kept for the whole interval so replicas run it once per interval together
+        match db
+            .acquire_lease(LEASE, &super::INSTANCE_ID, config.interval)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                tracing::error!("Failed to acquire reconciliation lease: {e}");
+                continue;
+            }
+        }
+        if let Err(e) = reconcile(&db, &config).await {
+            tracing::error!("Status reconciliation failed: {e}");
+        }
+    }
+}
+
+pub async fn reconcile(db: &Db, config: &Config) -> anyhow::Result<Summary> {
+    let started = Instant::now();
+    let since = db::now() - config.window.as_secs() as i64;
+    let mut limiter = tokio::time::interval(Duration::from_secs(1) / config.rate);
+    let mut summary = Summary::default();
+    let mut after_id = 0;
+    loop {
+        let batch = db
+            .transactions_updated_since(since, after_id, BATCH_SIZE)
+            .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after_id = last.id;
+        for transaction in batch {
+            limiter.tick().await;
+            summary.checked += 1;
+            match check(db, &transaction).await {
+                Ok(found) => {
+                    summary.discrepancies += found.len() as u64;
+                    summary.fixed += found.iter().filter(|(_, fixed)| *fixed).count() as u64;
+                }
+                Err(e) => {
+                    summary.failed += 1;
+                    tracing::warn!(reference = %transaction.gateway_reference, "Failed to reconcile transaction: {e}");
+                }
+            }
+        }
+    }
+    tracing::info!(
+        checked = summary.checked,
+        discrepancies = summary.discrepancies,
+        fixed = summary.fixed,
+        failed = summary.failed,
+        elapsed = started.elapsed().as_secs(),
+        "Status reconciliation finished"
+    );
+    Ok(summary)
+}
+
+/// Compare the transaction with the gateway and the last callback, fix what is safe to fix.
+///
+/// Returns found discrepancies and whether they were fixed
+async fn check(db: &Db, transaction: &Transaction) -> anyhow::Result<Vec<(&'static str, bool)>> {
+    let ctx = RequestContext::new(&transaction.settings());
+    let mut span = InteractionSpan::enter();
+    let response = ctx.status(&transaction.gateway_reference, &mut span).await;
+    if let Err(e) = db
+        .insert_interaction_log(
+            &transaction.gateway_reference,
+            &span.interaction_log("status"),
+        )
+        .await
+    {
+        tracing::error!("Failed to store interaction log: {e}");
+    }
+    let response = response?;
+    let paid = response.data.money()?;
+    let segura_status = response.data.status.as_str().to_string();
+    let post_settlement = response.data.status.effect() == StatusEffect::PostSettlement;
+    let gateway_status = transaction.outcome(response.data.status.into());
+
+    let mut found = Vec::new();
+    let mut record = async |kind: &'static str, details: Option<String>, fixed: bool| {
+        found.push((kind, fixed));
+        tracing::warn!(reference = %transaction.gateway_reference, kind, fixed, details, "Found transaction discrepancy");
+        db.insert_discrepancy(NewDiscrepancy {
+            gateway_reference: &transaction.gateway_reference,
+            kind,
+            our_status: Some(transaction.status),
+            gateway_status: Some(gateway_status),
+            details: details.as_deref(),
+            fixed,
+        })
+        .await
+    };
+
+    if transaction.status == connect::Status::Pending && gateway_status.is_final() {
+        // Callback from the gateway was lost, callback to Gateway.Connect is sent with the fix.
+        // Amount mismatches are recorded and handled by the amount check
+        let details = (gateway_status == connect::Status::Declined).then_some(response.message);
+        let verdict = check_amount(db, transaction, gateway_status, details, &paid).await?;
+        let fixed = match verdict {
+            Verdict::Apply(status, details) => {
+                apply_status(db, transaction, status, details).await?
+            }
+            Verdict::Hold => false,
+        };
+        record("status_pending", None, fixed).await?;
+        return Ok(found);
+    }
+
+    if !paid.matches(&transaction.money()) {
+        let details = format!("gateway amount {} {}", paid.major(), paid.currency);
+        record("amount_differs", Some(details), false).await?;
+    }
+    if post_settlement {
+        let details = format!("{segura_status} {}", response.message);
+        record("post_settlement", Some(details), false).await?;
+    } else if transaction.status != gateway_status {
+        record("status_differs", Some(response.message), false).await?;
+    }
+    match db.last_callback(&transaction.gateway_reference).await? {
+        Some((status, _)) if status != transaction.status => {
+            let details = format!("last callback status {}", status.as_str());
+            record("callback_differs", Some(details), false).await?;
+        }
+        // Callback is queued again unless the outbox is still retrying it
+        Some((status, false)) if status.is_final() => {
+            let fixed = match notify(db, transaction, status, transaction.status_details.clone())
+                .await
+            {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::warn!(reference = %transaction.gateway_reference, "Failed to resend callback: {e}");
+                    false
+                }
+            };
+            record("callback_not_delivered", None, fixed).await?;
+        }
+        _ => {}
+    }
+    Ok(found)
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use crate::db::{self, Db};
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Retention policy of the stored data, nothing is purged by default
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Time between purges, disabled when zero
+    pub interval: Duration,
+    /// Days customer data (email, ip, country, card fingerprint, interaction logs) is kept for
+    pub pii_days: Option<u32>,
+    /// Days gateway id mappings of final transactions are kept for
+    pub mapping_days: Option<u32>,
+    /// Days saved and vaulted cards are kept for
+    pub card_days: Option<u32>,
+}
+
+/// Amounts of purged records
+#[derive(Debug, Default)]
+pub struct Purged {
+    pub customer_data: u64,
+    pub interaction_logs: u64,
+    pub mappings: u64,
+    pub cards: u64,
+}
+
+impl Config {
+    /// Read `RETENTION_INTERVAL` (seconds), `RETENTION_PII_DAYS`, `RETENTION_MAPPING_DAYS` and
+    /// `RETENTION_CARD_DAYS`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let days = |name: &str| -> anyhow::Result<Option<u32>> {
+            std::env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(|e| anyhow::anyhow!("{name} must be amount of days: {e}"))
+        };
+        Ok(Self {
+            interval: super::env_secs("RETENTION_INTERVAL", 60 * 60)?,
+            pii_days: days("RETENTION_PII_DAYS")?,
+            mapping_days: days("RETENTION_MAPPING_DAYS")?,
+            card_days: days("RETENTION_CARD_DAYS")?,
+        })
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.pii_days.is_none() && self.mapping_days.is_none() && self.card_days.is_none()
+    }
+}
+
+pub async fn run(db: Db, config: Config) {
+    if config.interval.is_zero() || config.is_disabled() {
+        tracing::info!("Scheduled data purge is disabled");
+        return;
+    }
+    let mut interval = tokio::time::interval(config.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match super::with_lease(&db, "job:retention", purge(&db, &config)).await {
+            Ok(Some(Err(e))) | Err(e) => tracing::error!("Data purge failed: {e}"),
+            Ok(_) => {}
+        }
+    }
+}
+
+/// Purge data that is older than the retention policy allows
+pub async fn purge(db: &Db, config: &Config) -> sqlx::Result<Purged> {
+    let now = db::now();
+    let mut purged = Purged::default();
+    if let Some(days) = config.pii_days {
+        let before = now - i64::from(days) * DAY;
+        purged.customer_data = db.purge_customer_data(before).await?;
+        purged.interaction_logs = db.purge_interaction_logs(before).await?;
+    }
+    if let Some(days) = config.mapping_days {
+        purged.mappings = db.purge_mappings(now - i64::from(days) * DAY).await?;
+    }
+    if let Some(days) = config.card_days {
+        purged.cards = db.purge_cards(now - i64::from(days) * DAY).await?;
+    }
+    tracing::info!(
+        customer_data = purged.customer_data,
+        interaction_logs = purged.interaction_logs,
+        mappings = purged.mappings,
+        cards = purged.cards,
+        "Purged expired data"
+    );
+    Ok(purged)
+}
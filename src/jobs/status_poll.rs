@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use crate::{
+    db::{self, Db},
+    gateway::sync::sync_transaction,
+};
+
+/// Transactions fetched from the database at once
+const BATCH_SIZE: i64 = 100;
+
+/// Polling of the gateway for transactions whose callback never arrived
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Time between polls, disabled when zero
+    pub interval: Duration,
+    /// Time given to the gateway callback before the status is polled
+    pub delay: Duration,
+    /// Transactions older than this are not polled anymore
+    pub max_age: Duration,
+}
+
+impl Config {
+    /// Read `STATUS_POLL_INTERVAL`, `STATUS_POLL_DELAY` and `STATUS_POLL_MAX_AGE` (seconds)
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            interval: super::env_secs("STATUS_POLL_INTERVAL", 60)?,
+            delay: super::env_secs("STATUS_POLL_DELAY", 10 * 60)?,
+            max_age: super::env_secs("STATUS_POLL_MAX_AGE", 24 * 60 * 60)?,
+        })
+    }
+}
+
+pub async fn run(db: Db, config: Config) {
+    if config.interval.is_zero() {
+        tracing::info!("Status polling is disabled");
+        return;
+    }
+    let mut interval = tokio::time::interval(config.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll(&db, &config).await {
+            tracing::error!("Status poll failed: {e}");
+        }
+    }
+}
+
+async fn poll(db: &Db, config: &Config) -> anyhow::Result<()> {
+    let now = db::now();
+    let mut after_id = 0;
+    loop {
+        let transactions = db
+            .pending_transactions(
+                now - config.max_age.as_secs() as i64,
+                now - config.delay.as_secs() as i64,
+                after_id,
+                BATCH_SIZE,
+            )
+            .await?;
+        let Some(last) = transactions.last() else {
+            break;
+        };
+        after_id = last.id;
+        for transaction in transactions {
+            let resource = format!("transaction:{}", transaction.gateway_reference);
+            match super::with_lease(db, &resource, sync_transaction(db, &transaction)).await? {
+                Some(Ok(status)) => {
+                    tracing::debug!(reference = %transaction.gateway_reference, ?status, "Polled transaction status")
+                }
+                Some(Err(e)) => {
+                    tracing::warn!(reference = %transaction.gateway_reference, "Failed to poll transaction status: {e}")
+                }
+                None => {
+                    tracing::debug!(reference = %transaction.gateway_reference, "Transaction is claimed by another instance")
+                }
+            }
+        }
+    }
+    Ok(())
+}
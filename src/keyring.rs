@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+/// Id assigned to the key configured with the single key variable (e.g. `SIGN_KEY`)
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Set of versioned keys.
+///
+/// New data is signed or encrypted with the active key, the rest stay available by their id.
+#[derive(Debug, Clone)]
+pub struct KeyRing {
+    active: String,
+    keys: BTreeMap<String, [u8; 32]>,
+}
+
+/// How keys are written in the configuration
+#[derive(Debug, Clone, Copy)]
+pub enum KeyEncoding {
+    /// Hex encoded 32 bytes
+    Hex,
+    /// 32 ASCII characters used as is
+    Raw,
+}
+
+impl KeyEncoding {
+    fn decode(&self, value: &str) -> anyhow::Result<[u8; 32]> {
+        let bytes = match self {
+            KeyEncoding::Hex => hex::decode(value)?,
+            KeyEncoding::Raw => value.as_bytes().to_vec(),
+        };
+        bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("key must be 32 bytes long"))
+    }
+}
+
+impl KeyRing {
+    /// Build the ring from `{prefix}S` (`id:key,id:key`), `{prefix}` (single key with
+    /// [DEFAULT_KEY_ID] id) and `{prefix}_ID` (active key id) env variables.
+    ///
+    /// Returns `None` when no keys are configured.
+    pub fn from_env(prefix: &str, encoding: KeyEncoding) -> anyhow::Result<Option<Self>> {
+        let var = |name: String| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Self::parse(
+            var(format!("{prefix}S")).as_deref(),
+            var(prefix.to_string()).as_deref(),
+            var(format!("{prefix}_ID")).as_deref(),
+            encoding,
+        )
+        .map_err(|e| anyhow::anyhow!("{prefix} configuration: {e}"))
+    }
+
+    /// Same as [KeyRing::from_env] with already read variables
+    pub fn parse(
+        list: Option<&str>,
+        single: Option<&str>,
+        active: Option<&str>,
+        encoding: KeyEncoding,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut keys = BTreeMap::new();
+        for entry in list.unwrap_or_default().split(',').map(str::trim) {
+            if entry.is_empty() {
+                continue;
+            }
+            let Some((id, key)) = entry.split_once(':') else {
+                anyhow::bail!("key entry must be in id:key format");
+            };
+            if keys.insert(id.to_string(), encoding.decode(key)?).is_some() {
+                anyhow::bail!("key {id} is defined twice");
+            }
+        }
+        if let Some(key) = single {
+            if keys.contains_key(DEFAULT_KEY_ID) {
+                anyhow::bail!("key {DEFAULT_KEY_ID} is defined twice");
+            }
+            keys.insert(DEFAULT_KEY_ID.to_string(), encoding.decode(key)?);
+        }
+        let active = match active {
+            Some(id) if keys.contains_key(id) => id.to_string(),
+            Some(id) => anyhow::bail!("active key {id} is not defined"),
+            None if keys.len() > 1 => anyhow::bail!("active key id must be set"),
+            None => match keys.keys().next() {
+                Some(id) => id.clone(),
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(Self { active, keys }))
+    }
+
+    pub fn active(&self) -> (&str, &[u8; 32]) {
+        (&self.active, &self.keys[&self.active])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8; 32])> {
+        self.keys.iter().map(|(id, key)| (id.as_str(), key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_KEY_ID, KeyEncoding, KeyRing};
+
+    const OLD: &str = "e7403b3c0d76a35312e7cc65eeb75808";
+    const NEW: &str = "vhfrnepuogjvhfrarbivzogjvhfrehfg";
+
+    #[test]
+    fn single_key() {
+        let ring = KeyRing::parse(None, Some(OLD), None, KeyEncoding::Raw)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ring.active(),
+            (DEFAULT_KEY_ID, OLD.as_bytes().try_into().unwrap())
+        );
+        assert!(
+            KeyRing::parse(None, None, None, KeyEncoding::Raw)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn rotated_keys() {
+        let list = format!("2025:{OLD}, 2026:{NEW}");
+        let ring = KeyRing::parse(Some(&list), None, Some("2026"), KeyEncoding::Raw)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ring.active().0, "2026");
+        let keys: Vec<_> = ring.iter().collect();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0], ("2025", OLD.as_bytes().try_into().unwrap()));
+
+        // Legacy single key stays available next to the new ones
+        let ring = KeyRing::parse(
+            Some(&format!("2026:{NEW}")),
+            Some(OLD),
+            Some("2026"),
+            KeyEncoding::Raw,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(
+            ring.iter()
+                .any(|(id, key)| id == DEFAULT_KEY_ID && key == OLD.as_bytes())
+        );
+    }
+
+    #[test]
+    fn invalid_configuration() {
+        let list = format!("2025:{OLD},2026:{NEW}");
+        // Ambiguous active key
+        assert!(KeyRing::parse(Some(&list), None, None, KeyEncoding::Raw).is_err());
+        assert!(KeyRing::parse(Some(&list), None, Some("2027"), KeyEncoding::Raw).is_err());
+        assert!(KeyRing::parse(Some("2025:short"), None, None, KeyEncoding::Raw).is_err());
+        assert!(KeyRing::parse(Some("2025"), None, None, KeyEncoding::Raw).is_err());
+        assert!(KeyRing::parse(Some(&format!("a:{OLD}")), None, None, KeyEncoding::Hex).is_err());
+        assert!(
+            KeyRing::parse(
+                Some(&format!("a:{OLD},a:{NEW}")),
+                None,
+                Some("a"),
+                KeyEncoding::Raw
+            )
+            .is_err()
+        );
+    }
+}
@@ -4,21 +4,42 @@
 //! - [connect] (gateway.connect API surface)
 #![doc = include_str!("../README.md")]
 
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+};
 
 use axum::Router;
 use tracing_subscriber::EnvFilter;
 
+/// Internal API for support staff
+mod admin;
+/// Card related helpers that don't depend on a particular gateway
+mod card;
+/// Maintenance commands
+mod cli;
 /// Implementation of `gateway.connect`
 ///
 /// This module defines the types and endpoints to communicate with the `Gateway.Connect` API.
 mod connect;
 
 mod db;
+/// Transaction exports for finance
+mod export;
 /// Gateway integration implementation
 ///
 /// This module defines the types and methods to communicate with an external gateway. In this case it is SeguraPay
 mod gateway;
+mod jobs;
+/// Versioned keys
+mod keyring;
+/// Currency amounts in minor and major units
+mod money;
+/// Pre-authorization risk rules
+mod risk;
+/// Encryption of secrets stored at rest
+mod secret;
+mod settlement;
 mod state;
 
 #[tokio::main]
@@ -32,29 +53,116 @@ async fn main() {
         Ok(p) => tracing::info!(path = %p.display(), "Loaded environment variables from .env file"),
         Err(e) => tracing::warn!("Failed to environment variables from .env: {e}"),
     };
+    let command = match cli::Command::from_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    // Configuration read on first use is checked before anything is served
+    gateway::sync::MismatchPolicy::global();
+    std::sync::LazyLock::force(&card::vault::VAULT);
+    std::sync::LazyLock::force(&card::fingerprint::FINGERPRINT_KEYS);
+    risk::RiskRules::global();
+    card::bin::BinTable::global();
     let db = db::Db::connect().await.expect("database is not available");
-    let state = state::AppState::new(db);
+    if let Some(command) = command {
+        if let Err(e) = command.run(db).await {
+            tracing::error!("Command failed: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    jobs::spawn(db.clone()).expect("background jobs configuration is valid");
+    let dispute_auth = connect::auth::InboundAuth::from_env("DISPUTE")
+        .expect("dispute auth configuration is valid");
+    if dispute_auth.is_disabled() {
+        tracing::warn!(
+            "Dispute notification authentication is not configured, disputes are not forwarded"
+        );
+    }
+    let state = state::AppState {
+        forward_disputes: !dispute_auth.is_disabled(),
+        ..state::AppState::new(db)
+    };
+    let auth = connect::auth::InboundAuth::from_env("CONNECT")
+        .expect("inbound auth configuration is valid");
+    if auth.is_disabled() {
+        tracing::warn!("Connect API inbound authentication is not configured");
+    }
+    let admin_auth =
+        connect::auth::InboundAuth::from_env("ADMIN").expect("admin auth configuration is valid");
 
-    let app = Router::new()
-        .merge(connect::api::router())
-        .nest("/gateway", gateway::api::router())
+    // Internal API receives raw card data, public one only talks to the gateway.
+    // Each has its own middleware stack so they can be exposed separately.
+    let mut connect_app = connect::api::router(Arc::new(auth));
+    if admin_auth.is_disabled() {
+        tracing::warn!("Admin API authentication is not configured, admin API is disabled");
+    } else {
+        connect_app = connect_app.nest("/admin", admin::api::router(Arc::new(admin_auth)));
+    }
+    let connect_app = connect_app
+        .layer(tower_http::trace::TraceLayer::new_for_http())
+        .with_state(state.clone());
+    let gateway_app = Router::new()
+        .nest("/gateway", gateway::api::router(Arc::new(dispute_auth)))
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .with_state(state);
 
-    let port: u16 = std::env::var("PORT")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(4206);
-
-    let listener = tokio::net::TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))
-        .await
-        .unwrap();
-
-    tracing::info!("Serving on port {port}");
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async {
-            let _ = tokio::signal::ctrl_c().await;
-        })
-        .await
-        .unwrap();
+    let connect_addr = listen_addr("CONNECT_ADDR");
+    let gateway_addr = listen_addr("GATEWAY_ADDR");
+
+    if connect_addr.is_none() && gateway_addr.is_none() {
+        let port: u16 = std::env::var("PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4206);
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into();
+        serve("combined", addr, connect_app.merge(gateway_app))
+            .await
+            .unwrap();
+        return;
+    }
+
+    let connect = async {
+        match connect_addr {
+            Some(addr) => serve("connect", addr, connect_app).await,
+            None => {
+                tracing::warn!("CONNECT_ADDR is not defined, Connect API is disabled");
+                Ok(())
+            }
+        }
+    };
+    let gateway = async {
+        match gateway_addr {
+            Some(addr) => serve("gateway", addr, gateway_app).await,
+            None => {
+                tracing::warn!("GATEWAY_ADDR is not defined, gateway callbacks are disabled");
+                Ok(())
+            }
+        }
+    };
+    tokio::try_join!(connect, gateway).unwrap();
+}
+
+fn listen_addr(var: &str) -> Option<SocketAddr> {
+    let value = std::env::var(var).ok().filter(|v| !v.is_empty())?;
+    match value.parse() {
+        Ok(addr) => Some(addr),
+        Err(e) => panic!("{var} must be a socket address like 127.0.0.1:4206: {e}"),
+    }
+}
+
+async fn serve(name: &str, addr: SocketAddr, app: Router) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Serving {name} listener on {addr}");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await
 }
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+
+/// Currencies with other than 2 decimal places, ISO 4217
+const EXPONENTS: &[(&str, u32)] = &[
+    ("BHD", 3),
+    ("BIF", 0),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("DJF", 0),
+    ("GNF", 0),
+    ("IQD", 3),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("OMR", 3),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("TND", 3),
+    ("UGX", 0),
+    ("UYI", 0),
+    ("UYW", 4),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+];
+
+/// Decimal places of the currency, 2 for unknown ones
+pub fn exponent(currency: &str) -> u32 {
+    EXPONENTS
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(currency.trim()))
+        .map_or(2, |(_, exponent)| *exponent)
+}
+
+/// Amount in minor units of the currency, the way Gateway.Connect and the database keep it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Money {
+    pub amount: i64,
+    pub currency: String,
+}
+
+impl Money {
+    pub fn new(amount: i64, currency: impl Into<String>) -> Self {
+        Self {
+            amount,
+            currency: currency.into(),
+        }
+    }
+
+    /// Parse amount in major units like `10.50`. Fails on more decimal places than the currency
+    /// has unless they are zeros, signs and overflows
+    pub fn from_major(value: &str, currency: impl Into<String>) -> Option<Self> {
+        let currency = currency.into();
+        let exponent = exponent(&currency) as usize;
+        let value = value.trim();
+        let (major, fraction) = value.split_once('.').unwrap_or((value, ""));
+        let fraction = fraction.trim_end_matches('0');
+        let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if major.is_empty() || !digits(major) || !digits(fraction) || fraction.len() > exponent {
+            return None;
+        }
+        let major: i64 = major.parse().ok()?;
+        let fraction: i64 = format!("{fraction:0<exponent$}").parse().unwrap_or(0);
+        let amount = major
+            .checked_mul(10i64.pow(exponent as u32))?
+            .checked_add(fraction)?;
+        Some(Self { amount, currency })
+    }
+
+    /// Amount in major units with all decimal places of the currency, like `10.50`
+    pub fn major(&self) -> String {
+        let exponent = exponent(&self.currency);
+        let sign = if self.amount < 0 { "-" } else { "" };
+        let amount = self.amount.unsigned_abs();
+        if exponent == 0 {
+            return format!("{sign}{amount}");
+        }
+        let unit = 10u64.pow(exponent);
+        format!(
+            "{sign}{}.{:0width$}",
+            amount / unit,
+            amount % unit,
+            width = exponent as usize
+        )
+    }
+
+    /// Same amount and currency, currency case is ignored
+    pub fn matches(&self, other: &Money) -> bool {
+        self.amount == other.amount && self.currency.eq_ignore_ascii_case(&other.currency)
+    }
+}
+
+/// Amount in major units as the gateway sends it, either a JSON number or a string.
+///
+/// Kept as decimal text so it converts to [Money] exactly once the currency is known
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct MajorAmount(String);
+
+impl MajorAmount {
+    pub fn money(&self, currency: &str) -> anyhow::Result<Money> {
+        Money::from_major(&self.0, currency)
+            .ok_or_else(|| anyhow::anyhow!("invalid {currency} amount {}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for MajorAmount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = MajorAmount;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("decimal amount")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(MajorAmount(v.to_string()))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(MajorAmount(v.to_string()))
+            }
+
+            // Shortest representation that reads back as the same float, i.e. the decimal
+            // the gateway has written
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(MajorAmount(v.to_string()))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(MajorAmount(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MajorAmount, Money, exponent};
+
+    fn major(amount: i64, currency: &str) -> String {
+        Money::new(amount, currency).major()
+    }
+
+    fn minor(value: &str, currency: &str) -> Option<i64> {
+        Money::from_major(value, currency).map(|money| money.amount)
+    }
+
+    #[test]
+    fn zero_decimal() {
+        assert_eq!(exponent("JPY"), 0);
+        assert_eq!(exponent("xof"), 0);
+        assert_eq!(major(1050, "JPY"), "1050");
+        assert_eq!(major(-5, "XOF"), "-5");
+        assert_eq!(minor("1050", "JPY"), Some(1050));
+        assert_eq!(minor("1050.00", "JPY"), Some(1050));
+        assert_eq!(minor("10.5", "JPY"), None);
+    }
+
+    #[test]
+    fn two_decimal() {
+        assert_eq!(exponent("USD"), 2);
+        assert_eq!(exponent("NGN"), 2);
+        assert_eq!(major(1050, "USD"), "10.50");
+        assert_eq!(major(5, "EUR"), "0.05");
+        assert_eq!(major(0, "EUR"), "0.00");
+        assert_eq!(major(-120, "USD"), "-1.20");
+        assert_eq!(minor("10.50", "USD"), Some(1050));
+        assert_eq!(minor("10.5", "USD"), Some(1050));
+        assert_eq!(minor("7", "USD"), Some(700));
+        assert_eq!(minor(" 0.05 ", "USD"), Some(5));
+        assert_eq!(minor("1.005", "USD"), None);
+        assert_eq!(minor("-1.50", "USD"), None);
+        assert_eq!(minor("1,50", "USD"), None);
+        assert_eq!(minor(".50", "USD"), None);
+        assert_eq!(minor("99999999999999999999", "USD"), None);
+    }
+
+    #[test]
+    fn three_decimal() {
+        assert_eq!(exponent("KWD"), 3);
+        assert_eq!(exponent("BHD"), 3);
+        assert_eq!(major(10505, "KWD"), "10.505");
+        assert_eq!(major(5, "BHD"), "0.005");
+        assert_eq!(minor("10.505", "KWD"), Some(10505));
+        assert_eq!(minor("10.5", "KWD"), Some(10500));
+        assert_eq!(minor("10.5055", "KWD"), None);
+    }
+
+    #[test]
+    fn gateway_amounts() {
+        let amount = |json: &str| serde_json::from_str::<MajorAmount>(json).unwrap();
+        assert_eq!(
+            amount("10.5").money("USD").unwrap(),
+            Money::new(1050, "USD")
+        );
+        assert_eq!(amount("0.1").money("EUR").unwrap().amount, 10);
+        assert_eq!(amount("1.005").money("KWD").unwrap().amount, 1005);
+        assert_eq!(amount("1500").money("JPY").unwrap().amount, 1500);
+        assert_eq!(amount("\"20.00\"").money("USD").unwrap().amount, 2000);
+        assert!(amount("10.05").money("JPY").is_err());
+        assert!(serde_json::from_str::<MajorAmount>("true").is_err());
+        assert!(Money::new(1050, "usd").matches(&Money::new(1050, "USD")));
+        assert!(!Money::new(1050, "USD").matches(&Money::new(1050, "EUR")));
+    }
+}
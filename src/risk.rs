@@ -0,0 +1,358 @@
+use std::{path::Path, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    card::{bin, fingerprint::CardFingerprint},
+    db::{Db, TransactionFilter, now},
+    money::Money,
+};
+
+/// Rules from `RISK_RULES_PATH`, every payment is allowed without it
+static RISK_RULES: LazyLock<RiskRules> = LazyLock::new(|| {
+    let Ok(path) = std::env::var("RISK_RULES_PATH") else {
+        return RiskRules::default();
+    };
+    let rules = RiskRules::from_path(&path).expect("risk rules configuration is valid");
+    tracing::info!(%path, rules = rules.rules.len(), "Loaded risk rules");
+    rules
+});
+
+/// Outcome of the risk assessment, the strictest matched rule wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Allow,
+    /// Payment goes on, it is recorded for manual review
+    Review,
+    /// Payment is not sent to the gateway
+    Decline,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Assessment {
+    pub decision: Decision,
+    /// Names of the matched rules
+    pub rules: Vec<String>,
+}
+
+/// Payment as seen by the rules
+#[derive(Debug)]
+pub struct RiskInput<'a> {
+    /// Merchant gateway account
+    pub client_id: &'a str,
+    pub money: &'a Money,
+    pub country: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub ip: Option<&'a str>,
+    /// Unknown for hosted payments
+    pub card: Option<&'a CardFingerprint>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiskRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rule {
+    name: String,
+    /// Rule applies only to payments of the merchant account, to all of them when not set
+    client_id: Option<String>,
+    /// [Decision::Decline] or [Decision::Review]
+    action: Decision,
+    #[serde(flatten)]
+    check: Check,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Check {
+    /// Amount in minor units of the currency is out of bounds
+    Amount {
+        currency: String,
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Currency(List),
+    /// Country sent by Gateway.Connect
+    Country(List),
+    /// Card issuing country from the BIN table, unknown for hosted payments
+    IssuerCountry(List),
+    /// Card BIN starts with one of the prefixes
+    Bin {
+        deny: Vec<String>,
+    },
+    /// At least `limit` payments with the same value within `window` seconds before this one
+    Velocity {
+        by: VelocityKey,
+        limit: i64,
+        window: i64,
+    },
+}
+
+/// Allow or deny list, matches values missing in the allow list or present in the deny one.
+/// Unknown value matches only the allow list
+#[derive(Debug, Deserialize)]
+struct List {
+    allow: Option<Vec<String>>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum VelocityKey {
+    CardFingerprint,
+    Email,
+    Ip,
+}
+
+impl List {
+    fn matches(&self, value: Option<&str>) -> bool {
+        let contains = |list: &[String]| {
+            value.is_some_and(|value| list.iter().any(|item| item.eq_ignore_ascii_case(value)))
+        };
+        self.allow.as_deref().is_some_and(|allow| !contains(allow)) || contains(&self.deny)
+    }
+}
+
+impl RiskRules {
+    /// Parse rules from JSON `{"rules": [...]}`
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let rules: Self = serde_json::from_str(json)?;
+        for rule in &rules.rules {
+            if rule.action == Decision::Allow {
+                anyhow::bail!("rule {}: action must be decline or review", rule.name);
+            }
+        }
+        Ok(rules)
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Rules configured for this process
+    pub fn global() -> &'static Self {
+        &RISK_RULES
+    }
+
+    /// Evaluate every rule that applies to the payment
+    pub async fn assess(&self, db: &Db, payment: &RiskInput<'_>) -> Assessment {
+        let mut assessment = Assessment {
+            decision: Decision::Allow,
+            rules: Vec::new(),
+        };
+        for rule in &self.rules {
+            if rule
+                .client_id
+                .as_ref()
+                .is_some_and(|client_id| client_id != payment.client_id)
+            {
+                continue;
+            }
+            let matched = match rule.check.matches(db, payment).await {
+                Ok(matched) => matched,
+                Err(e) => {
+                    // Payments are not blocked by the database failure
+                    tracing::error!(rule = %rule.name, "Failed to evaluate risk rule: {e}");
+                    false
+                }
+            };
+            if matched {
+                assessment.decision = assessment.decision.max(rule.action);
+                assessment.rules.push(rule.name.clone());
+            }
+        }
+        assessment
+    }
+}
+
+impl Check {
+    async fn matches(&self, db: &Db, payment: &RiskInput<'_>) -> sqlx::Result<bool> {
+        let matched = match self {
+            Check::Amount { currency, min, max } => {
+                payment.money.currency.eq_ignore_ascii_case(currency)
+                    && (min.is_some_and(|min| payment.money.amount < min)
+                        || max.is_some_and(|max| payment.money.amount > max))
+            }
+            Check::Currency(list) => list.matches(Some(&payment.money.currency)),
+            Check::Country(list) => list.matches(payment.country),
+            Check::IssuerCountry(list) => list.matches(
+                payment
+                    .card
+                    .and_then(|card| bin::lookup(&card.bin))
+                    .and_then(|info| info.country.as_deref()),
+            ),
+            Check::Bin { deny } => payment
+                .card
+                .is_some_and(|card| deny.iter().any(|prefix| card.bin.starts_with(prefix))),
+            Check::Velocity { by, limit, window } => {
+                let mut filter = TransactionFilter {
+                    created_from: Some(now() - window),
+                    ..Default::default()
+                };
+                let value = match by {
+                    VelocityKey::CardFingerprint => {
+                        payment.card.and_then(|card| card.fingerprint.as_deref())
+                    }
+                    VelocityKey::Email => payment.email,
+                    VelocityKey::Ip => payment.ip,
+                };
+                let Some(value) = value.map(str::to_string) else {
+                    return Ok(false);
+                };
+                match by {
+                    VelocityKey::CardFingerprint => filter.card_fingerprint = Some(value),
+                    VelocityKey::Email => filter.customer_email = Some(value),
+                    VelocityKey::Ip => filter.customer_ip = Some(value),
+                }
+                db.count_transactions(&filter).await? >= *limit
+            }
+        };
+        Ok(matched)
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::{Decision, RiskInput, RiskRules};
+    use crate::{
+        card::fingerprint::CardFingerprint,
+        db::{
+            NewTransaction,
+            tests::{new_transaction, sqlite},
+        },
+        money::Money,
+    };
+
+    const RULES: &str = r#"{"rules": [
+        {"name": "merchant limit", "client_id": "client", "action": "decline", "type": "amount", "currency": "USD", "max": 100000},
+        {"name": "currencies", "action": "decline", "type": "currency", "allow": ["USD", "EUR"]},
+        {"name": "countries", "action": "review", "type": "country", "deny": ["ng"]},
+        {"name": "bins", "action": "decline", "type": "bin", "deny": ["4485"]},
+        {"name": "email velocity", "action": "review", "type": "velocity", "by": "email", "limit": 2, "window": 3600}
+    ]}"#;
+
+    #[tokio::test]
+    async fn assess() {
+        let db = sqlite().await;
+        let rules = RiskRules::from_json(RULES).unwrap();
+        let money = Money::new(1050, "USD");
+        let payment = RiskInput {
+            client_id: "client",
+            money: &money,
+            country: Some("GH"),
+            email: Some("customer@example.com"),
+            ip: None,
+            card: None,
+        };
+        let assess = |payment| {
+            let (db, rules) = (&db, &rules);
+            async move { rules.assess(db, &payment).await }
+        };
+
+        let assessment = assess(RiskInput { ..payment }).await;
+        assert_eq!(assessment.decision, Decision::Allow);
+        assert!(assessment.rules.is_empty());
+
+        // Limit applies only to the merchant
+        let large = Money::new(100001, "USD");
+        let assessment = assess(RiskInput {
+            money: &large,
+            ..payment
+        })
+        .await;
+        assert_eq!(assessment.decision, Decision::Decline);
+        assert_eq!(assessment.rules, ["merchant limit"]);
+        let assessment = assess(RiskInput {
+            money: &large,
+            client_id: "other",
+            ..payment
+        })
+        .await;
+        assert_eq!(assessment.decision, Decision::Allow);
+
+        let gbp = Money::new(1050, "GBP");
+        let assessment = assess(RiskInput {
+            money: &gbp,
+            ..payment
+        })
+        .await;
+        assert_eq!(assessment.rules, ["currencies"]);
+
+        // Decline wins over review
+        let card = CardFingerprint::with_key(None, "4485081333091151");
+        let assessment = assess(RiskInput {
+            country: Some("NG"),
+            card: Some(&card),
+            ..payment
+        })
+        .await;
+        assert_eq!(assessment.decision, Decision::Decline);
+        assert_eq!(assessment.rules, ["countries", "bins"]);
+        let assessment = assess(RiskInput {
+            country: None,
+            ..payment
+        })
+        .await;
+        assert_eq!(assessment.decision, Decision::Allow);
+
+        for reference in ["first", "second"] {
+            db.insert_transaction(NewTransaction {
+                customer_email: Some("customer@example.com"),
+                ..new_transaction(reference)
+            })
+            .await
+            .unwrap();
+        }
+        let assessment = assess(RiskInput { ..payment }).await;
+        assert_eq!(assessment.decision, Decision::Review);
+        assert_eq!(assessment.rules, ["email velocity"]);
+    }
+
+    #[tokio::test]
+    async fn issuer_country() {
+        let db = sqlite().await;
+        let rules = RiskRules::from_json(
+            r#"{"rules": [{"name": "issuers", "action": "review", "type": "issuer_country", "deny": ["NG"]}]}"#,
+        )
+        .unwrap();
+        let money = Money::new(1050, "NGN");
+        let assess = |pan| {
+            let card = CardFingerprint::with_key(None, pan);
+            let (db, rules, money) = (&db, &rules, &money);
+            async move {
+                let payment = RiskInput {
+                    client_id: "client",
+                    money,
+                    country: Some("GH"),
+                    email: None,
+                    ip: None,
+                    card: Some(&card),
+                };
+                rules.assess(db, &payment).await.decision
+            }
+        };
+        assert_eq!(assess("5061000000000000").await, Decision::Review);
+        assert_eq!(assess("4485081333091151").await, Decision::Allow);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(
+            RiskRules::from_json(
+                r#"{"rules": [{"name": "x", "action": "allow", "type": "bin", "deny": []}]}"#
+            )
+            .is_err()
+        );
+        assert!(
+            RiskRules::from_json(
+                r#"{"rules": [{"name": "x", "action": "decline", "type": "unknown"}]}"#
+            )
+            .is_err()
+        );
+    }
+}
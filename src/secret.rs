@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit,
+    aead::{Aead, AeadCore, OsRng},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::keyring::{KeyEncoding, KeyRing};
+
+/// Prefix of every value sealed by [Secrets]
+const SEALED_PREFIX: &str = "enc:";
+/// `enc:v2:{key id}:{base64(nonce + ciphertext)}`
+const PREFIX: &str = "enc:v2:";
+/// Values sealed before key versioning: `enc:v1:{base64(nonce + ciphertext)}`
+const LEGACY_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+/// Purpose of the data key derived from the master key
+const DATA_KEY_INFO: &[u8] = b"segura-gateway/db-secrets";
+
+/// Encryption of secrets stored at rest.
+///
+/// Values are encrypted with AES-256-GCM using the data key derived from the active master key
+/// and are tagged with its id, so values sealed with older master keys remain readable.
+/// Without master keys values are stored as is, plaintext values are always readable
+/// so the rows written before encryption was enabled keep working.
+#[derive(Clone)]
+pub struct Secrets {
+    active: Option<String>,
+    ciphers: BTreeMap<String, Aes256Gcm>,
+}
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secrets")
+            .field("active", &self.active)
+            .field("keys", &self.ciphers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Secrets {
+    pub fn new(master_keys: Option<&KeyRing>) -> Self {
+        Self::with_purpose(master_keys, DATA_KEY_INFO)
+    }
+
+    /// Same as [Secrets::new] with data keys derived for another purpose, so values sealed for
+    /// one purpose can't be opened as the other
+    pub fn with_purpose(master_keys: Option<&KeyRing>, info: &[u8]) -> Self {
+        let Some(master_keys) = master_keys else {
+            return Self {
+                active: None,
+                ciphers: BTreeMap::new(),
+            };
+        };
+        let ciphers = master_keys
+            .iter()
+            .map(|(id, master_key)| {
+                let mut data_key = [0u8; 32];
+                Hkdf::<Sha256>::new(None, master_key)
+                    .expand(info, &mut data_key)
+                    .expect("32 bytes is a valid hkdf output length");
+                (id.to_string(), Aes256Gcm::new(&data_key.into()))
+            })
+            .collect();
+        Self {
+            active: Some(master_keys.active().0.to_string()),
+            ciphers,
+        }
+    }
+
+    /// Read hex encoded master keys from `DB_MASTER_KEYS`, `DB_MASTER_KEY` and `DB_MASTER_KEY_ID`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let master_keys = KeyRing::from_env("DB_MASTER_KEY", KeyEncoding::Hex)?;
+        if master_keys.is_none() {
+            tracing::warn!("DB_MASTER_KEY is not defined, secrets are stored in plaintext");
+        }
+        Ok(Self::new(master_keys.as_ref()))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Whether the value is not sealed with the active master key
+    pub fn needs_reseal(&self, value: &str) -> bool {
+        match &self.active {
+            Some(active) => value
+                .strip_prefix(PREFIX)
+                .and_then(|rest| rest.split_once(':'))
+                .is_none_or(|(id, _)| id != active),
+            None => false,
+        }
+    }
+
+    /// Encrypt the value if master key is configured
+    pub fn seal(&self, value: &str) -> String {
+        let Some(active) = &self.active else {
+            return value.to_string();
+        };
+        let cipher = &self.ciphers[active];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, value.as_bytes())
+            .expect("encryption of in memory buffer is infallible");
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        format!("{PREFIX}{active}:{}", BASE64_STANDARD.encode(payload))
+    }
+
+    /// Decrypt sealed value, plaintext is returned as is
+    pub fn open(&self, value: &str) -> anyhow::Result<String> {
+        if !value.starts_with(SEALED_PREFIX) {
+            return Ok(value.to_string());
+        }
+        if !self.is_enabled() {
+            anyhow::bail!("value is encrypted but DB_MASTER_KEY is not defined");
+        }
+        if let Some(rest) = value.strip_prefix(PREFIX) {
+            let Some((id, encoded)) = rest.split_once(':') else {
+                anyhow::bail!("encrypted value has no key id");
+            };
+            let Some(cipher) = self.ciphers.get(id) else {
+                anyhow::bail!("master key {id} is not defined");
+            };
+            return decrypt(cipher, encoded);
+        }
+        if let Some(encoded) = value.strip_prefix(LEGACY_PREFIX) {
+            // Legacy values don't record the key, authentication tag tells which one fits
+            return self
+                .ciphers
+                .values()
+                .find_map(|cipher| decrypt(cipher, encoded).ok())
+                .ok_or_else(|| anyhow::anyhow!("failed to decrypt value"));
+        }
+        anyhow::bail!("unknown encrypted value format")
+    }
+}
+
+fn decrypt(cipher: &Aes256Gcm, encoded: &str) -> anyhow::Result<String> {
+    let payload = BASE64_STANDARD.decode(encoded)?;
+    if payload.len() < NONCE_LEN {
+        anyhow::bail!("encrypted value is truncated");
+    }
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .decrypt(nonce.into(), ciphertext)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt value"))?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::aead::{Aead, AeadCore, OsRng};
+    use base64::{Engine, prelude::BASE64_STANDARD};
+
+    use super::Secrets;
+    use crate::keyring::{KeyEncoding, KeyRing};
+
+    const OLD_KEY: &str = "0707070707070707070707070707070707070707070707070707070707070707";
+    const NEW_KEY: &str = "0808080808080808080808080808080808080808080808080808080808080808";
+
+    fn ring(list: &str, active: &str) -> KeyRing {
+        KeyRing::parse(Some(list), None, Some(active), KeyEncoding::Hex)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let secrets = Secrets::new(Some(&ring(&format!("old:{OLD_KEY}"), "old")));
+        let sealed = secrets.seal("5178831496700b3634e4");
+        assert!(sealed.starts_with("enc:v2:old:"));
+        assert!(!secrets.needs_reseal(&sealed));
+        assert_ne!(secrets.seal("5178831496700b3634e4"), sealed);
+        assert_eq!(secrets.open(&sealed).unwrap(), "5178831496700b3634e4");
+        // Rows written before encryption was enabled
+        assert_eq!(secrets.open("plaintext").unwrap(), "plaintext");
+        assert!(secrets.needs_reseal("plaintext"));
+
+        let other = Secrets::new(Some(&ring(&format!("new:{NEW_KEY}"), "new")));
+        assert!(other.open(&sealed).is_err());
+        assert!(Secrets::new(None).open(&sealed).is_err());
+        assert_eq!(Secrets::new(None).seal("plaintext"), "plaintext");
+        assert!(!Secrets::new(None).needs_reseal("plaintext"));
+    }
+
+    #[test]
+    fn rotation() {
+        let old = Secrets::new(Some(&ring(&format!("old:{OLD_KEY}"), "old")));
+        let sealed = old.seal("merchant key");
+
+        let rotated = Secrets::new(Some(&ring(&format!("old:{OLD_KEY},new:{NEW_KEY}"), "new")));
+        assert!(rotated.needs_reseal(&sealed));
+        assert_eq!(rotated.open(&sealed).unwrap(), "merchant key");
+        let resealed = rotated.seal(&rotated.open(&sealed).unwrap());
+        assert!(resealed.starts_with("enc:v2:new:"));
+        assert!(!rotated.needs_reseal(&resealed));
+    }
+
+    #[test]
+    fn purpose() {
+        let keys = ring(&format!("old:{OLD_KEY}"), "old");
+        let sealed = Secrets::with_purpose(Some(&keys), b"vault").seal("4485081333091151");
+        assert!(Secrets::new(Some(&keys)).open(&sealed).is_err());
+        assert_eq!(
+            Secrets::with_purpose(Some(&keys), b"vault")
+                .open(&sealed)
+                .unwrap(),
+            "4485081333091151"
+        );
+    }
+
+    #[test]
+    fn legacy_format() {
+        let rotated = Secrets::new(Some(&ring(&format!("old:{OLD_KEY},new:{NEW_KEY}"), "new")));
+        // Value sealed before key ids were recorded
+        let cipher = &rotated.ciphers["old"];
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut payload = nonce.to_vec();
+        payload.extend(cipher.encrypt(&nonce, b"legacy".as_slice()).unwrap());
+        let legacy = format!("enc:v1:{}", BASE64_STANDARD.encode(payload));
+
+        assert_eq!(rotated.open(&legacy).unwrap(), "legacy");
+        assert!(rotated.needs_reseal(&legacy));
+    }
+}
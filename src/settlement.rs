@@ -0,0 +1,336 @@
+//! Reconciliation of stored transactions against Segura settlement reports.
+//!
+//! Report is a CSV file with `orderReference`, `paymentReference`, `amount` (major units),
+//! `currency` and `status` (`SUCCESS`, `FAILED` or `PENDING`) columns, other columns are ignored.
+//! Rows are matched by `orderReference` (gateway reference) and then by `paymentReference`
+//! (our client reference).
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    connect,
+    db::{Db, Transaction, TransactionFilter},
+    gateway::sync::{Verdict, apply_status, check_amount},
+    money::Money,
+};
+
+/// Transactions fetched from the database at once when looking for ones missing in the report
+const BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportRow {
+    #[serde(default)]
+    order_reference: String,
+    #[serde(default)]
+    payment_reference: String,
+    amount: String,
+    currency: String,
+    status: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    /// Report row has no stored transaction
+    Missing,
+    /// Approved transaction of the reconciled period is absent in the report
+    NotInReport,
+    StatusDiffers,
+    /// Amount or currency differs
+    AmountDiffers,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Mismatch {
+    pub kind: MismatchKind,
+    pub reference: String,
+    pub our_status: Option<connect::Status>,
+    pub report_status: Option<connect::Status>,
+    /// Minor units
+    pub our_amount: Option<i64>,
+    /// Minor units
+    pub report_amount: Option<i64>,
+    pub our_currency: Option<String>,
+    pub report_currency: Option<String>,
+    /// Pending transaction was finalized with the report status and Gateway.Connect was notified
+    pub corrected: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// Amount of report rows
+    pub rows: usize,
+    /// Rows that match stored transactions completely
+    pub matched: usize,
+    pub corrected: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Finalize pending transactions with the report status and send callbacks for them
+    pub correct: bool,
+    /// Period the report covers as unix timestamps, approved transactions created within it
+    /// must be present in the report
+    pub period: Option<(i64, i64)>,
+}
+
+/// Compare the report with stored transactions
+pub async fn reconcile(
+    db: &Db,
+    report: impl std::io::Read,
+    options: &Options,
+) -> anyhow::Result<Report> {
+    let mut result = Report::default();
+    let mut seen = std::collections::HashSet::new();
+    for (line, row) in csv::Reader::from_reader(report)
+        .deserialize::<ReportRow>()
+        .enumerate()
+    {
+        // Header is the first line
+        let row = row.map_err(|e| anyhow::anyhow!("report line {}: {e}", line + 2))?;
+        result.rows += 1;
+        let status = parse_status(&row.status).ok_or_else(|| {
+            anyhow::anyhow!("report line {}: unknown status {}", line + 2, row.status)
+        })?;
+        let money = Money::from_major(&row.amount, &row.currency).ok_or_else(|| {
+            anyhow::anyhow!("report line {}: invalid amount {}", line + 2, row.amount)
+        })?;
+        let amount = money.amount;
+        let Some(transaction) = find(db, &row).await? else {
+            result.mismatches.push(Mismatch {
+                kind: MismatchKind::Missing,
+                reference: first_non_empty(&row.order_reference, &row.payment_reference)
+                    .to_string(),
+                our_status: None,
+                report_status: Some(status),
+                our_amount: None,
+                report_amount: Some(amount),
+                our_currency: None,
+                report_currency: Some(row.currency),
+                corrected: false,
+            });
+            continue;
+        };
+        seen.insert(transaction.gateway_reference.clone());
+
+        let kind = if transaction.status != status {
+            MismatchKind::StatusDiffers
+        } else if transaction.amount != amount
+            || !transaction.currency.eq_ignore_ascii_case(&row.currency)
+        {
+            MismatchKind::AmountDiffers
+        } else {
+            result.matched += 1;
+            continue;
+        };
+        let corrected = kind == MismatchKind::StatusDiffers
+            && options.correct
+            && transaction.status == connect::Status::Pending
+            && status.is_final()
+            && correct(db, &transaction, status, &money).await;
+        result.corrected += usize::from(corrected);
+        result.mismatches.push(Mismatch {
+            kind,
+            reference: transaction.gateway_reference,
+            our_status: Some(transaction.status),
+            report_status: Some(status),
+            our_amount: Some(transaction.amount),
+            report_amount: Some(amount),
+            our_currency: Some(transaction.currency),
+            report_currency: Some(row.currency),
+            corrected,
+        });
+    }
+
+    if let Some((from, to)) = options.period {
+        let filter = TransactionFilter {
+            status: Some(connect::Status::Approved),
+            created_from: Some(from),
+            created_to: Some(to),
+            ..Default::default()
+        };
+        let mut after_id = 0;
+        loop {
+            let batch = db.transactions_after(&filter, after_id, BATCH_SIZE).await?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            after_id = last.id;
+            for transaction in batch {
+                if seen.contains(&transaction.gateway_reference) {
+                    continue;
+                }
+                result.mismatches.push(Mismatch {
+                    kind: MismatchKind::NotInReport,
+                    reference: transaction.gateway_reference,
+                    our_status: Some(transaction.status),
+                    report_status: None,
+                    our_amount: Some(transaction.amount),
+                    report_amount: None,
+                    our_currency: Some(transaction.currency),
+                    report_currency: None,
+                    corrected: false,
+                });
+            }
+        }
+    }
+
+    tracing::info!(
+        rows = result.rows,
+        matched = result.matched,
+        mismatches = result.mismatches.len(),
+        corrected = result.corrected,
+        "Reconciled settlement report"
+    );
+    Ok(result)
+}
+
+async fn find(db: &Db, row: &ReportRow) -> sqlx::Result<Option<Transaction>> {
+    if !row.order_reference.is_empty()
+        && let Some(transaction) = db
+            .get_transaction_by_gateway_reference(&row.order_reference)
+            .await?
+    {
+        return Ok(Some(transaction));
+    }
+    if row.payment_reference.is_empty() {
+        return Ok(None);
+    }
+    db.get_transaction(&row.payment_reference).await
+}
+
+/// Finalize pending transaction with the report status, failure is reported as not corrected.
+///
+/// Reported amount is checked the same way as the gateway one, held transaction is not corrected
+async fn correct(
+    db: &Db,
+    transaction: &Transaction,
+    status: connect::Status,
+    paid: &Money,
+) -> bool {
+    let details = (status == connect::Status::Declined)
+        .then(|| "Declined according to settlement report".to_string());
+    let result = match check_amount(db, transaction, status, details, paid).await {
+        Ok(Verdict::Apply(status, details)) => apply_status(db, transaction, status, details).await,
+        Ok(Verdict::Hold) => Ok(false),
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(corrected) => corrected,
+        Err(e) => {
+            tracing::error!(reference = %transaction.gateway_reference, "Failed to correct transaction status: {e}");
+            false
+        }
+    }
+}
+
+fn first_non_empty<'a>(a: &'a str, b: &'a str) -> &'a str {
+    if a.is_empty() { b } else { a }
+}
+
+fn parse_status(value: &str) -> Option<connect::Status> {
+    match value.trim().to_ascii_uppercase().as_str() {
+        "SUCCESS" => Some(connect::Status::Approved),
+        "FAILED" => Some(connect::Status::Declined),
+        "PENDING" => Some(connect::Status::Pending),
+        _ => None,
+    }
+}
+
+/// Write mismatches as CSV
+pub fn write_csv(report: &Report, writer: impl std::io::Write) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for mismatch in &report.mismatches {
+        writer.serialize(mismatch)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_status;
+    use crate::connect;
+
+    #[test]
+    fn statuses() {
+        assert_eq!(parse_status("success"), Some(connect::Status::Approved));
+        assert_eq!(parse_status("REFUNDED"), None);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn mismatches() {
+        use super::{MismatchKind, Options, reconcile};
+        use crate::db::{
+            NewTransaction,
+            tests::{new_transaction, sqlite},
+        };
+
+        let db = sqlite().await;
+        for (reference, amount, status) in [
+            ("matched", 1000, connect::Status::Approved),
+            ("pending", 1000, connect::Status::Pending),
+            ("amount", 1000, connect::Status::Approved),
+            ("absent", 1000, connect::Status::Approved),
+        ] {
+            db.insert_transaction(NewTransaction {
+                client_reference: &format!("client-{reference}"),
+                amount,
+                ..new_transaction(reference)
+            })
+            .await
+            .unwrap();
+            if status.is_final() {
+                db.finalize_transaction(reference, status, None)
+                    .await
+                    .unwrap();
+            }
+        }
+        let report = "orderReference,paymentReference,amount,currency,status,fee
+matched,,10.00,USD,SUCCESS,0.1
+,client-pending,10.00,USD,SUCCESS,0.1
+amount,,9.99,USD,SUCCESS,0.1
+unknown,,5.00,USD,FAILED,0
+";
+        let options = Options {
+            correct: false,
+            period: Some((0, i64::MAX)),
+        };
+        let result = reconcile(&db, report.as_bytes(), &options).await.unwrap();
+        assert_eq!(result.rows, 4);
+        assert_eq!(result.matched, 1);
+        assert_eq!(result.corrected, 0);
+        let kinds: Vec<_> = result
+            .mismatches
+            .iter()
+            .map(|m| (m.kind, m.reference.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (MismatchKind::StatusDiffers, "pending"),
+                (MismatchKind::AmountDiffers, "amount"),
+                (MismatchKind::Missing, "unknown"),
+                (MismatchKind::NotInReport, "absent"),
+            ]
+        );
+
+        let invalid = "orderReference,amount,currency,status\nmatched,ten,USD,SUCCESS\n";
+        assert!(reconcile(&db, invalid.as_bytes(), &options).await.is_err());
+
+        // Approval of another amount is held for review like the gateway one
+        let report = "orderReference,amount,currency,status\npending,9.99,USD,SUCCESS\n";
+        let options = Options {
+            correct: true,
+            period: None,
+        };
+        let result = reconcile(&db, report.as_bytes(), &options).await.unwrap();
+        assert_eq!(result.corrected, 0);
+        let record = db.get_transaction_record("pending").await.unwrap().unwrap();
+        assert_eq!(record.status, connect::Status::Pending);
+        assert!(record.on_hold);
+    }
+}
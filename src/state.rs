@@ -3,10 +3,22 @@ use crate::db::Db;
 #[derive(Debug, Clone, axum::extract::FromRef)]
 pub struct AppState {
     pub db: Db,
+    /// Make the first delivery attempt of queued callbacks right away instead of leaving them
+    /// to the outbox job
+    #[from_ref(skip)]
+    pub deliver_callbacks: bool,
+    /// Dispute notifications are authenticated and forwarded to Gateway.Connect, otherwise they
+    /// are only stored for review
+    #[from_ref(skip)]
+    pub forward_disputes: bool,
 }
 
 impl AppState {
     pub fn new(db: Db) -> Self {
-        Self { db }
+        Self {
+            db,
+            deliver_callbacks: true,
+            forward_disputes: false,
+        }
     }
 }